@@ -0,0 +1,75 @@
+//! Compares the hand-written lexer with the regex tokenizer it replaced, over
+//! the lines of `mal_tests/perf*.mal` as they would be fed to `read` one at a
+//! time.
+//!
+//! Run with `cargo bench --bench tokenize`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use make_a_lisp_rs::lexer::tokenize;
+
+const ITERATIONS: u32 = 2_000;
+
+/// The previous tokenizer, which compiled its regex on every call.
+fn tokenize_regex(input: &str) -> Vec<(String, usize)> {
+    let re = regex::Regex::new(
+        r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"#,
+    )
+    .unwrap();
+    re.captures_iter(input)
+        .map(|cap| (cap[1].to_string(), cap.get(1).unwrap().start()))
+        .collect()
+}
+
+fn time(lines: &[String], f: impl Fn(&str) -> usize) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        for line in lines {
+            black_box(f(black_box(line)));
+        }
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let mut paths: Vec<_> = std::fs::read_dir("mal_tests")
+        .expect("run from the crate root")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            name.starts_with("perf") && name.ends_with(".mal")
+        })
+        .collect();
+    paths.sort();
+
+    let mut lines = Vec::new();
+    for path in &paths {
+        let source = std::fs::read_to_string(path).unwrap();
+        lines.extend(source.lines().map(str::to_owned));
+    }
+
+    // Both tokenizers must agree before their timings mean anything
+    for line in &lines {
+        let expected = tokenize_regex(line);
+        let found: Vec<_> = tokenize(line)
+            .into_iter()
+            .map(|token| (token.text, token.offset))
+            .collect();
+        assert_eq!(found, expected, "tokenizers disagree on {:?}", line);
+    }
+
+    let regex = time(&lines, |line| tokenize_regex(line).len());
+    let lexer = time(&lines, |line| tokenize(line).len());
+    let per_line = |total: Duration| total / (ITERATIONS * lines.len() as u32);
+
+    println!(
+        "{} lines from {} files, {} iterations",
+        lines.len(),
+        paths.len(),
+        ITERATIONS
+    );
+    println!("regex: {:>10.2?} per line", per_line(regex));
+    println!("lexer: {:>10.2?} per line", per_line(lexer));
+    println!("speedup: {:.1}x", regex.as_secs_f64() / lexer.as_secs_f64());
+}
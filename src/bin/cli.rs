@@ -1,30 +1,313 @@
-use std::ops::DerefMut;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
 
 use color_eyre::owo_colors::OwoColorize;
 use color_eyre::Result;
-use make_a_lisp_rs::rep;
+use make_a_lisp_rs::error::MalError;
+use make_a_lisp_rs::lexer::{tokenize, Token, TokenKind};
+use make_a_lisp_rs::types::MalData;
+use make_a_lisp_rs::{eval, print, re, read, read_all, Env, SPECIAL_FORMS};
+use rustyline::completion::Completer;
 use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{
+    Cmd, CompletionType, ConditionalEventHandler, Config, Context, Editor, Event, EventContext,
+    EventHandler, Helper, KeyEvent, Movement, RepeatCount,
+};
+
+/// Editor support for the REPL, backed by the environment being evaluated in.
+struct MalHelper {
+    env: Rc<RefCell<Env>>,
+}
+
+impl Completer for MalHelper {
+    type Candidate = String;
+
+    /// Completes the symbol under the cursor with the bound symbols and the
+    /// special forms.
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        // Find the start of the token ending at the cursor, if any
+        let token = tokenize(&line[..pos])
+            .pop()
+            .filter(|token| token.offset + token.text.len() == pos);
+        let start = match token {
+            Some(token) if token.kind == TokenKind::Atom => token.offset,
+            Some(token) if token.kind != TokenKind::Special => return Ok((pos, vec![])),
+            _ => pos,
+        };
+        let prefix = &line[start..pos];
+
+        let mut candidates: Vec<String> = self
+            .env
+            .borrow()
+            .keys()
+            .into_iter()
+            .chain(SPECIAL_FORMS.iter().map(|form| form.to_string()))
+            .filter(|symbol| symbol.starts_with(prefix))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for MalHelper {
+    type Hint = String;
+}
+
+impl Highlighter for MalHelper {
+    /// Colors the input by token class and highlights the bracket matching the
+    /// one at the cursor.
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let tokens = tokenize(line);
+        // The bracket at the cursor, its partner, and whether they pair up
+        let brackets = matching_bracket(&tokens, pos).map(|(at, other)| {
+            let matched = closes(&tokens[at.min(other)].text, &tokens[at.max(other)].text);
+            (at, other, matched)
+        });
+
+        let mut highlighted = String::with_capacity(line.len() * 2);
+        let mut end = 0;
+        for (i, token) in tokens.iter().enumerate() {
+            // Keep the whitespace and commas between tokens as they are
+            highlighted.push_str(&line[end..token.offset]);
+            end = token.offset + token.text.len();
+
+            let bracket = match brackets {
+                Some((at, other, matched)) if i == at || i == other => Some(matched),
+                _ => None,
+            };
+            let text = token.text.as_str();
+            let painted = match (bracket, token.kind) {
+                (Some(true), _) => text.bold().yellow().to_string(),
+                (Some(false), _) => text.bold().red().to_string(),
+                (_, TokenKind::String) => text.green().to_string(),
+                (_, TokenKind::Comment) => text.bright_black().to_string(),
+                (_, TokenKind::Atom) if text.starts_with(':') => text.magenta().to_string(),
+                (_, TokenKind::Atom) if text.parse::<i64>().is_ok() => text.cyan().to_string(),
+                (_, TokenKind::Atom) if SPECIAL_FORMS.contains(&text) => {
+                    text.bold().blue().to_string()
+                }
+                _ => text.to_owned(),
+            };
+            highlighted.push_str(&painted);
+        }
+        highlighted.push_str(&line[end..]);
+        Cow::Owned(highlighted)
+    }
+
+    // Moving the cursor can change which brackets are highlighted
+    fn highlight_char(&self, _line: &str, _pos: usize, _forced: bool) -> bool {
+        true
+    }
+}
+
+// Incomplete forms are continued by the loop in `main` rather than by a
+// validator, since rustyline cannot show a continuation prompt on the lines a
+// validator adds
+impl Validator for MalHelper {}
+
+/// Makes Ctrl-C clear a partly typed form rather than leave the REPL.
+struct AbandonForm;
+
+impl ConditionalEventHandler for AbandonForm {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        if ctx.line().is_empty() {
+            None
+        } else {
+            Some(Cmd::Kill(Movement::WholeBuffer))
+        }
+    }
+}
+
+impl Helper for MalHelper {}
+
+fn is_bracket(token: &Token) -> bool {
+    token.kind == TokenKind::Special && "()[]{}".contains(token.text.as_str())
+}
+
+fn closes(opening: &str, closing: &str) -> bool {
+    matches!((opening, closing), ("(", ")") | ("[", "]") | ("{", "}"))
+}
+
+/// Finds the bracket under or just before the cursor and the bracket matching
+/// it, as indices into `tokens`.
+fn matching_bracket(tokens: &[Token], pos: usize) -> Option<(usize, usize)> {
+    let at = tokens
+        .iter()
+        .position(|token| is_bracket(token) && token.offset == pos)
+        .or_else(|| {
+            tokens
+                .iter()
+                .position(|token| is_bracket(token) && token.offset + 1 == pos)
+        })?;
+    let opening = "([{".contains(tokens[at].text.as_str());
+
+    let mut depth = 0;
+    let mut step = |i: usize| {
+        if !is_bracket(&tokens[i]) {
+            return false;
+        }
+        if "([{".contains(tokens[i].text.as_str()) == opening {
+            depth += 1;
+        } else {
+            depth -= 1;
+        }
+        depth == 0
+    };
+    if opening {
+        (at..tokens.len()).find(|&i| step(i))
+    } else {
+        (0..=at).rev().find(|&i| step(i))
+    }
+    .map(|other| (at, other))
+}
+
+/// A colon-prefixed REPL command, handled before the input is read as mal.
+enum Command<'a> {
+    /// Lists every binding with its value.
+    Env,
+    /// Evaluates the forms in a file.
+    Load(&'a str),
+    /// Evaluates a form and reports how long it took.
+    Time(&'a str),
+    /// Starts over with a fresh root environment.
+    Reset,
+    Quit,
+}
+
+impl<'a> Command<'a> {
+    /// Parses a command line. Anything else, including a lone keyword such as
+    /// `:foo`, is left to the reader.
+    fn parse(line: &'a str) -> Option<Self> {
+        let line = line.trim();
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (line, ""),
+        };
+        match name {
+            ":env" => Some(Command::Env),
+            ":load" => Some(Command::Load(argument)),
+            ":time" => Some(Command::Time(argument)),
+            ":reset" => Some(Command::Reset),
+            ":quit" => Some(Command::Quit),
+            _ => None,
+        }
+    }
+}
 
 fn main() -> Result<()> {
     color_eyre::install()?;
-    let mut rl = DefaultEditor::new()?;
-    let environment = make_a_lisp_rs::Env::new(None);
-    make_a_lisp_rs::load_builtins(environment.borrow_mut().deref_mut());
+    let mut environment = Env::new(None);
+    make_a_lisp_rs::load_builtins(environment.clone());
+
+    // Run a script if one was given, binding the remaining arguments to *ARGV*
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(script) = args.get(1) {
+        let argv = args[2..]
+            .iter()
+            .map(|arg| MalData::String(arg.clone()))
+            .collect();
+        environment
+            .borrow_mut()
+            .set("*ARGV*".to_owned(), MalData::List(argv, None));
+        if !run_file(script, &environment) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    clear_results(&environment);
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut rl: Editor<MalHelper, DefaultHistory> = Editor::with_config(config)?;
+    rl.set_helper(Some(MalHelper {
+        env: environment.clone(),
+    }));
+    rl.bind_sequence(
+        KeyEvent::ctrl('C'),
+        EventHandler::Conditional(Box::new(AbandonForm)),
+    );
+
+    // Keep the history across sessions; there is none yet on the first run
+    let history_path = dirs::data_dir().map(|dir| dir.join("make-a-lisp-rs").join("history.txt"));
+    if let Some(path) = &history_path {
+        let _ = rl.load_history(path);
+    }
+
+    // The lines of a form that is still missing closing brackets or quotes
+    let mut pending = String::new();
 
     loop {
-        // Read the input
-        let input = rl.readline("user> ");
-        match input {
+        // Read the input, continuing the pending form if there is one
+        let prompt = if pending.is_empty() {
+            "user> "
+        } else {
+            "   ...> "
+        };
+        match rl.readline(prompt) {
             Ok(line) => {
-                // Save the input in the history
-                rl.add_history_entry(line.as_str())?;
+                if let Some(command) = Command::parse(&line).filter(|_| pending.is_empty()) {
+                    rl.add_history_entry(line.as_str())?;
+                    match command {
+                        Command::Env => print_env(&environment),
+                        Command::Load("") => eprintln!("{}", "usage: :load <file>".red()),
+                        Command::Load(path) => {
+                            run_file(path, &environment);
+                        }
+                        Command::Time(form) => {
+                            let start = Instant::now();
+                            eval_print(form, &environment);
+                            eprintln!("{}", format!("elapsed: {:?}", start.elapsed()).dimmed());
+                        }
+                        Command::Reset => {
+                            environment = Env::new(None);
+                            make_a_lisp_rs::load_builtins(environment.clone());
+                            clear_results(&environment);
+                            if let Some(helper) = rl.helper_mut() {
+                                helper.env = environment.clone();
+                            }
+                        }
+                        Command::Quit => break,
+                    }
+                    continue;
+                }
 
-                // Process the line
-                match rep(line, environment.clone()) {
-                    Ok(output) => println!("{}", output),
-                    Err(e) => eprintln!("{}", e.red()),
+                pending.push_str(&line);
+                if let Err(e) = read(pending.clone()) {
+                    if e.is_incomplete() {
+                        pending.push('\n');
+                        continue;
+                    }
                 }
+                let source = std::mem::take(&mut pending);
+
+                // Save the whole form in the history
+                rl.add_history_entry(source.as_str())?;
+
+                eval_print(&source, &environment);
+            }
+            // Ctrl-C on an empty line abandons a pending form rather than
+            // leaving the REPL
+            Err(ReadlineError::Interrupted) if !pending.is_empty() => {
+                pending.clear();
             }
             Err(ReadlineError::Interrupted | ReadlineError::Eof) => {
                 break;
@@ -36,5 +319,109 @@ fn main() -> Result<()> {
         }
     }
 
+    if let Some(path) = &history_path {
+        let saved = match path.parent() {
+            Some(dir) => std::fs::create_dir_all(dir).map_err(ReadlineError::from),
+            None => Ok(()),
+        }
+        .and_then(|()| rl.save_history(path));
+        if let Err(e) = saved {
+            eprintln!("{}", format!("could not save history: {}", e).red());
+        }
+    }
+
     Ok(())
 }
+
+/// Evaluates the form in `source` and prints the result, pointing at the input
+/// on reader errors.
+///
+/// Successful results are kept as `*1`, `*2` and `*3`, and the last error as
+/// `*e`.
+fn eval_print(source: &str, env: &Rc<RefCell<Env>>) {
+    let error = match re(source.to_owned(), env.clone()) {
+        Ok(output) => {
+            println!("{}", print(output.clone()));
+            let mut env = env.borrow_mut();
+            for (from, to) in [("*2", "*3"), ("*1", "*2")] {
+                let previous = env.get(from).unwrap_or(MalData::Nil);
+                env.set(to.to_owned(), previous);
+            }
+            env.set("*1".to_owned(), output);
+            return;
+        }
+        // Errors from `read-string` carry positions too, but only those in the
+        // input itself can be pointed at
+        Err(e) if read(source.to_owned()).is_err() => {
+            report_read_error(&e, source);
+            e
+        }
+        Err(e) => {
+            eprintln!("{}", e.red());
+            e
+        }
+    };
+    env.borrow_mut().set("*e".to_owned(), error.into_value());
+}
+
+/// Binds the REPL result history to nil in a fresh environment.
+fn clear_results(env: &Rc<RefCell<Env>>) {
+    let mut env = env.borrow_mut();
+    for symbol in ["*1", "*2", "*3", "*e"] {
+        env.set(symbol.to_owned(), MalData::Nil);
+    }
+}
+
+/// Evaluates every form in a file, stopping at the first error. Returns
+/// whether the whole file ran.
+fn run_file(path: &str, env: &Rc<RefCell<Env>>) -> bool {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("{}", format!("{}: {}", path, e).red());
+            return false;
+        }
+    };
+    let forms = match read_all(source.clone()) {
+        Ok(forms) => forms,
+        Err(e) => {
+            report_read_error(&e, &source);
+            return false;
+        }
+    };
+    for ast in forms {
+        if let Err(e) = eval(ast, env.clone()) {
+            eprintln!("{}", e.red());
+            return false;
+        }
+    }
+    true
+}
+
+/// Lists every binding visible from `env` with its value.
+fn print_env(env: &Rc<RefCell<Env>>) {
+    let env = env.borrow();
+    for key in env.keys() {
+        if let Some(value) = env.get(&key) {
+            println!("{} = {}", key.bold(), value);
+        }
+    }
+}
+
+/// Prints a reader error followed by the offending source line and a caret
+/// under the position the error was found at.
+fn report_read_error(error: &MalError, source: &str) {
+    eprintln!("{}", error.red());
+    let Some(position) = error.position() else {
+        return;
+    };
+    if let Some(line) = source.lines().nth(position.line - 1) {
+        let gutter = format!("{} | ", position.line);
+        eprintln!("{}{}", gutter.dimmed(), line);
+        eprintln!(
+            "{}{}",
+            " ".repeat(gutter.len() + position.column - 1),
+            "^".red()
+        );
+    }
+}
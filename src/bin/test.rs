@@ -1,6 +1,6 @@
-use std::{cell::RefCell, env, fmt::Display, ops::DerefMut, panic, rc::Rc};
+use std::{cell::RefCell, env, fmt::Display, panic, rc::Rc};
 
-use make_a_lisp_rs::{error::MalError, rep, Env};
+use make_a_lisp_rs::{capture_output, error::MalError, rep, Env};
 
 #[derive(Debug)]
 struct Section {
@@ -10,15 +10,46 @@ struct Section {
 
 #[derive(Debug, Clone)]
 enum ExpectedOutput {
+    /// A `;=>` line on its own, compared with the value of the input.
     Literal(String),
-    Regex(pcre2::bytes::Regex),
+    /// `;/` lines, possibly followed by a `;=>` line, matched against what the
+    /// input prints followed by its value.
+    Regex {
+        lines: Vec<String>,
+        regex: pcre2::bytes::Regex,
+    },
+}
+
+impl ExpectedOutput {
+    /// Builds the expected output from the `;/` and `;=>` lines of a case, as
+    /// the upstream `runtest.py` does.
+    fn parse(lines: Vec<String>) -> Self {
+        match lines.as_slice() {
+            [line] if line.starts_with(";=>") => ExpectedOutput::Literal(line[3..].to_owned()),
+            _ => {
+                let pattern = lines
+                    .iter()
+                    .map(|line| match line.strip_prefix(";=>") {
+                        Some(literal) => pcre2::escape(literal),
+                        None => line[2..].to_owned(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let regex = pcre2::bytes::RegexBuilder::new()
+                    .dotall(true)
+                    .build(&pattern)
+                    .unwrap();
+                ExpectedOutput::Regex { lines, regex }
+            }
+        }
+    }
 }
 
 impl Display for ExpectedOutput {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ExpectedOutput::Literal(literal) => write!(f, ";=>{}", literal),
-            ExpectedOutput::Regex(regex) => write!(f, ";/{}", regex.as_str()),
+            ExpectedOutput::Regex { lines, .. } => write!(f, "{}", lines.join("\n")),
         }
     }
 }
@@ -42,18 +73,26 @@ struct CaseRunConfig {
 }
 
 impl Case {
-    fn run(&self, config: CaseRunConfig, env: Rc<RefCell<Env>>) -> CaseOutput {
-        if config.skip_deferrable && self.deferrable {
-            return CaseOutput::Skip;
+    /// The meta flags set on the case, which make its failures soft.
+    fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![];
+        if self.soft {
+            flags.push("soft");
         }
-        if config.skip_soft && self.soft {
-            return CaseOutput::Skip;
+        if self.deferrable {
+            flags.push("deferrable");
         }
-        if config.skip_optional && self.optional {
-            return CaseOutput::Skip;
+        if self.optional {
+            flags.push("optional");
         }
+        flags
+    }
 
-        let result: Result<String, MalError> = {
+    /// Evaluates the input lines, returning the value of the last one as the
+    /// REPL would print it, and what the input printed along the way.
+    fn evaluate(&self, env: Rc<RefCell<Env>>) -> (String, String) {
+        // What the case prints would otherwise end up in the middle of a report
+        let (result, printed): (Result<String, MalError>, String) = capture_output(|| {
             let mut output = String::new();
             for line in self.input.iter() {
                 output = match rep(line.clone(), env.clone()) {
@@ -64,75 +103,645 @@ impl Case {
                 };
             }
             Ok(output)
-        };
+        });
 
-        let actual_output = match result {
+        let value = match result {
             Ok(output) => output,
             Err(e) => format!("{}", e),
         };
+        (value, printed)
+    }
+
+    fn run(&self, config: &CaseRunConfig, env: Rc<RefCell<Env>>) -> CaseOutput {
+        if config.skip_deferrable && self.deferrable {
+            return CaseOutput::Skip;
+        }
+        if config.skip_soft && self.soft {
+            return CaseOutput::Skip;
+        }
+        if config.skip_optional && self.optional {
+            return CaseOutput::Skip;
+        }
+
+        let (value, printed) = self.evaluate(env);
 
-        let passed = match self.expected_output.clone() {
-            ExpectedOutput::Literal(expected_output) => actual_output == expected_output,
-            ExpectedOutput::Regex(expected_output) => {
-                expected_output.is_match(actual_output.as_bytes()).unwrap()
+        let (passed, actual_output) = match &self.expected_output {
+            ExpectedOutput::Literal(expected_output) => (&value == expected_output, value),
+            ExpectedOutput::Regex { regex, .. } => {
+                let actual_output = printed + &value;
+                (
+                    regex.is_match(actual_output.as_bytes()).unwrap(),
+                    actual_output,
+                )
             }
         };
         if passed {
-            CaseOutput::Pass
+            CaseOutput::Pass(actual_output)
         } else {
-            CaseOutput::Fail(FailingCase {
-                input: self.input.clone(),
-                expected_output: self.expected_output.clone(),
-                actual_output,
-                line_number: self.line_number,
-                soft: self.soft,
-                optional: self.optional,
-                deferrable: self.deferrable,
-            })
+            CaseOutput::Fail(actual_output)
         }
     }
 }
 
 struct SectionOutput {
     title: String,
-    cases: Vec<CaseOutput>,
-}
-
-struct FailingCase {
-    input: Vec<String>,
-    expected_output: ExpectedOutput,
-    actual_output: String,
-    line_number: usize,
-    soft: bool,
-    optional: bool,
-    deferrable: bool,
+    cases: Vec<(Case, CaseOutput)>,
 }
 
 enum CaseOutput {
-    Pass,
+    Pass(String),
     Skip,
-    Fail(FailingCase),
+    Fail(String),
+}
+
+impl CaseOutput {
+    fn actual_output(&self) -> Option<&str> {
+        match self {
+            CaseOutput::Pass(actual_output) | CaseOutput::Fail(actual_output) => {
+                Some(actual_output)
+            }
+            CaseOutput::Skip => None,
+        }
+    }
+}
+
+const USAGE: &str = "usage: test [--skip-deferrable] [--skip-optional] [--skip-soft] \
+                     [--section <substring>] [--line <n>] [--format <text|tap|junit|json>] \
+                     [--output <file>] <file or directory>...";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Failing cases and summaries for people to read.
+    Text,
+    Tap,
+    Junit,
+    Json,
+}
+
+struct Options {
+    // Test files, and directories whose `.mal` files are all run
+    paths: Vec<String>,
+    config: CaseRunConfig,
+    // Only run sections whose title contains this
+    section: Option<String>,
+    // Only run the case starting on this line
+    line: Option<usize>,
+    format: Format,
+    // Where to write a machine-readable report, which otherwise shares stdout
+    // with anything the tests print
+    output: Option<String>,
+}
+
+impl Options {
+    /// Whether the case is picked out by `--section` and `--line`.
+    fn selects(&self, section: &Section, case: &Case) -> bool {
+        self.section
+            .as_ref()
+            .is_none_or(|filter| section.title.contains(filter.as_str()))
+            && self.line.is_none_or(|line| line == case.line_number)
+    }
+
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut paths = vec![];
+        let mut config = CaseRunConfig {
+            skip_deferrable: false,
+            skip_soft: false,
+            skip_optional: false,
+        };
+        let mut section = None;
+        let mut line = None;
+        let mut format = Format::Text;
+        let mut output = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--skip-deferrable" => config.skip_deferrable = true,
+                "--skip-optional" => config.skip_optional = true,
+                "--skip-soft" => config.skip_soft = true,
+                "--section" => {
+                    section = Some(args.next().ok_or("--section needs a substring")?);
+                }
+                "--line" => {
+                    let number = args.next().ok_or("--line needs a line number")?;
+                    let number = number
+                        .parse()
+                        .map_err(|_| format!("invalid line number: {}", number))?;
+                    line = Some(number);
+                }
+                "--format" => {
+                    format = match args.next().as_deref() {
+                        Some("text") => Format::Text,
+                        Some("tap") => Format::Tap,
+                        Some("junit") => Format::Junit,
+                        Some("json") => Format::Json,
+                        Some(other) => return Err(format!("unknown format: {}", other)),
+                        None => return Err("--format needs a format".to_owned()),
+                    };
+                }
+                "--output" => {
+                    output = Some(args.next().ok_or("--output needs a file")?);
+                }
+                flag if flag.starts_with("--") => return Err(format!("unknown option: {}", flag)),
+                _ => paths.push(arg),
+            }
+        }
+
+        if paths.is_empty() {
+            return Err("no test files given".to_owned());
+        }
+        if output.is_some() && format == Format::Text {
+            return Err("--output needs --format tap, junit or json".to_owned());
+        }
+        Ok(Self {
+            paths,
+            config,
+            section,
+            line,
+            format,
+            output,
+        })
+    }
+}
+
+/// Case counts for a file, or for every file run.
+#[derive(Default)]
+struct Summary {
+    cases: usize,
+    passed: usize,
+    skipped: usize,
+    soft_fails: usize,
+    hard_fails: usize,
+}
+
+impl Summary {
+    fn of(section_outputs: &[SectionOutput]) -> Self {
+        let mut summary = Summary::default();
+        for (case, output) in section_outputs.iter().flat_map(|section| &section.cases) {
+            summary.cases += 1;
+            match output {
+                CaseOutput::Pass(_) => summary.passed += 1,
+                CaseOutput::Skip => summary.skipped += 1,
+                CaseOutput::Fail(_) if !case.flags().is_empty() => summary.soft_fails += 1,
+                CaseOutput::Fail(_) => summary.hard_fails += 1,
+            }
+        }
+        summary
+    }
+
+    fn add(&mut self, other: &Summary) {
+        self.cases += other.cases;
+        self.passed += other.passed;
+        self.skipped += other.skipped;
+        self.soft_fails += other.soft_fails;
+        self.hard_fails += other.hard_fails;
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} cases, {} passed, {} skipped, {} soft fails, {} hard fails, {} total fails",
+            self.cases,
+            self.passed,
+            self.skipped,
+            self.soft_fails,
+            self.hard_fails,
+            self.hard_fails + self.soft_fails,
+        )
+    }
+}
+
+struct FileOutput {
+    path: String,
+    sections: Vec<SectionOutput>,
+    summary: Summary,
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let text = options.format == Format::Text;
+
+    // Directories stand for the test files directly inside them
+    let mut test_files = vec![];
+    let mut unreadable = 0;
+    for path in &options.paths {
+        match std::fs::read_dir(path) {
+            Ok(entries) => {
+                let mut files: Vec<String> = entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| {
+                        path.is_file() && path.extension().is_some_and(|ext| ext == "mal")
+                    })
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect();
+                files.sort();
+                test_files.extend(files);
+            }
+            Err(_) => test_files.push(path.clone()),
+        }
+    }
+
+    let mut file_outputs = vec![];
+    let mut total = Summary::default();
+    let mut any_selected = false;
+    // Library and script files such as those loaded by the tests have no cases
+    let mut without_cases = 0;
+    for test_file in &test_files {
+        let input = match std::fs::read_to_string(test_file) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", test_file, e);
+                unreadable += 1;
+                continue;
+            }
+        };
+        let sections = parse_sections(&input);
+        if sections.is_empty() {
+            without_cases += 1;
+            continue;
+        }
+        if text {
+            println!("Running test file: {}", test_file);
+        }
+        let (sections, selected) = run_test_file(sections, &options);
+        any_selected |= selected;
+        let summary = Summary::of(&sections);
+        if text {
+            print_failures(&sections);
+            println!("Summary: {}\n", summary);
+        }
+        total.add(&summary);
+        file_outputs.push(FileOutput {
+            path: test_file.clone(),
+            sections,
+            summary,
+        });
+    }
+
+    // A filter that matches nothing is most likely a typo
+    let filters: Vec<String> = [
+        options
+            .section
+            .as_ref()
+            .map(|section| format!("--section {}", section)),
+        options.line.map(|line| format!("--line {}", line)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !any_selected && !filters.is_empty() && !file_outputs.is_empty() {
+        eprintln!("No test case matches {}", filters.join(" "));
+        std::process::exit(2);
+    }
+
+    let report = match options.format {
+        Format::Text => None,
+        Format::Tap => Some(tap_report(&file_outputs)),
+        Format::Junit => Some(junit_report(&file_outputs, &total)),
+        Format::Json => Some(json_report(&file_outputs) + "\n"),
+    };
+    match (report, &options.output) {
+        (None, _) => {
+            if without_cases > 0 {
+                println!("Files without test cases skipped: {}", without_cases);
+            }
+            if file_outputs.len() > 1 {
+                println!("Total over {} files: {}", file_outputs.len(), total);
+            }
+        }
+        (Some(report), None) => print!("{}", report),
+        (Some(report), Some(path)) => {
+            if let Err(e) = std::fs::write(path, report) {
+                eprintln!("Could not write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Soft fails are expected while a step is in progress, hard fails are not
+    if total.hard_fails > 0 || unreadable > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Runs the cases of a parsed test file in a fresh environment, along with
+/// whether any of them were selected.
+///
+/// Cases left out by `--section` or `--line` are reported as skipped, but
+/// those before a selected case are still evaluated since it may depend on
+/// their definitions.
+fn run_test_file(sections: Vec<Section>, options: &Options) -> (Vec<SectionOutput>, bool) {
+    let env = make_a_lisp_rs::Env::new(None);
+    make_a_lisp_rs::load_builtins(env.clone());
+
+    let last_selected = sections
+        .iter()
+        .flat_map(|section| section.cases.iter().map(move |case| (section, case)))
+        .enumerate()
+        .filter(|(_, (section, case))| options.selects(section, case))
+        .map(|(i, _)| i)
+        .last();
+
+    let mut case_index = 0;
+    let section_outputs = sections
+        .iter()
+        .map(|section| SectionOutput {
+            title: section.title.clone(),
+            cases: section
+                .cases
+                .iter()
+                .map(|case| {
+                    let output = if options.selects(section, case) {
+                        case.run(&options.config, env.clone())
+                    } else {
+                        if last_selected.is_some_and(|last| case_index < last) {
+                            case.evaluate(env.clone());
+                        }
+                        CaseOutput::Skip
+                    };
+                    case_index += 1;
+                    (case.clone(), output)
+                })
+                .collect::<Vec<_>>(),
+        })
+        .collect::<Vec<_>>();
+    (section_outputs, last_selected.is_some())
+}
 
-    let test_file = args[1].clone();
-    println!("Running test file: {}", test_file);
+/// Prints the failing cases of a test file by section.
+fn print_failures(section_outputs: &[SectionOutput]) {
+    let mut num_cases_seen = 0;
+    for section_output in section_outputs {
+        let failing_cases = section_output
+            .cases
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (case, output))| match output {
+                CaseOutput::Fail(actual_output) => Some((i, case, actual_output)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !failing_cases.is_empty() {
+            println!("Section {}", section_output.title);
+            for (i, case, actual_output) in failing_cases {
+                let soft_flags = case.flags();
+                let soft_flags = if soft_flags.is_empty() {
+                    "".to_owned()
+                } else {
+                    format!(" ({})", soft_flags.join(", "))
+                };
+                println!(
+                    "Case {} (line {}){}:",
+                    num_cases_seen + i + 1,
+                    case.line_number,
+                    soft_flags
+                );
+                println!("Input> {}", case.input.join("\n"));
+                println!("Expected output {}", case.expected_output);
+                println!("Actual output ;=> {}", actual_output);
+            }
+            println!();
+        }
+        num_cases_seen += section_output.cases.len();
+    }
+}
+
+/// One case of one file, as reported in the machine-readable formats.
+struct CaseRecord<'a> {
+    file: &'a str,
+    section: &'a str,
+    case: &'a Case,
+    output: &'a CaseOutput,
+}
 
-    let input = std::fs::read_to_string(args[1].clone()).unwrap();
-    // println!("{}", input);
+impl CaseRecord<'_> {
+    fn status(&self) -> &'static str {
+        match self.output {
+            CaseOutput::Pass(_) => "pass",
+            CaseOutput::Skip => "skip",
+            CaseOutput::Fail(_) => "fail",
+        }
+    }
+
+    /// A one-line name for the case.
+    fn name(&self) -> String {
+        format!("{} (line {})", self.section, self.case.line_number)
+    }
+}
+
+fn records(file_output: &FileOutput) -> impl Iterator<Item = CaseRecord<'_>> {
+    file_output.sections.iter().flat_map(move |section| {
+        section.cases.iter().map(move |(case, output)| CaseRecord {
+            file: &file_output.path,
+            section: &section.title,
+            case,
+            output,
+        })
+    })
+}
+
+/// Test Anything Protocol output. Soft fails are marked TODO so that they do
+/// not count against the run, as with the exit code.
+fn tap_report(file_outputs: &[FileOutput]) -> String {
+    let mut report = String::from("TAP version 13\n");
+    let count: usize = file_outputs.iter().map(|file| file.summary.cases).sum();
+    report.push_str(&format!("1..{}\n", count));
+
+    let all_records = file_outputs.iter().flat_map(records);
+    for (i, record) in all_records.enumerate() {
+        let description = format!("{}: {}", record.file, record.name()).replace('#', "\\#");
+        let flags = record.case.flags();
+        let (result, directive) = match record.output {
+            CaseOutput::Pass(_) => ("ok", String::new()),
+            CaseOutput::Skip => ("ok", " # SKIP".to_owned()),
+            CaseOutput::Fail(_) if flags.is_empty() => ("not ok", String::new()),
+            CaseOutput::Fail(_) => ("not ok", format!(" # TODO {}", flags.join(", "))),
+        };
+        report.push_str(&format!(
+            "{} {} - {}{}\n",
+            result,
+            i + 1,
+            description,
+            directive
+        ));
+        if let CaseOutput::Fail(actual_output) = record.output {
+            // JSON strings are valid YAML scalars
+            report.push_str("  ---\n");
+            report.push_str(&format!(
+                "  input: {}\n",
+                json_string(&record.case.input.join("\n"))
+            ));
+            report.push_str(&format!(
+                "  expected: {}\n",
+                json_string(&record.case.expected_output.to_string())
+            ));
+            report.push_str(&format!("  actual: {}\n", json_string(actual_output)));
+            report.push_str("  ...\n");
+        }
+    }
+    report
+}
+
+/// JUnit XML output with a test suite per file. Soft fails are reported as
+/// skipped so that they do not count against the run, as with the exit code.
+fn junit_report(file_outputs: &[FileOutput], total: &Summary) -> String {
+    let mut report = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    report.push_str(&format!(
+        "<testsuites tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+        total.cases,
+        total.hard_fails,
+        total.skipped + total.soft_fails
+    ));
+    for file_output in file_outputs {
+        let summary = &file_output.summary;
+        report.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+            xml_escape(&file_output.path),
+            summary.cases,
+            summary.hard_fails,
+            summary.skipped + summary.soft_fails
+        ));
+        for record in records(file_output) {
+            report.push_str(&format!(
+                "    <testcase classname=\"{}\" name=\"{}\"",
+                xml_escape(record.file),
+                xml_escape(&record.name())
+            ));
+            let flags = record.case.flags();
+            match record.output {
+                CaseOutput::Pass(_) => report.push_str("/>\n"),
+                CaseOutput::Skip => report.push_str(">\n      <skipped/>\n    </testcase>\n"),
+                CaseOutput::Fail(actual_output) => {
+                    let details = format!(
+                        "input: {}\nexpected: {}\nactual: {}",
+                        record.case.input.join("\n"),
+                        record.case.expected_output,
+                        actual_output
+                    );
+                    let element = if flags.is_empty() {
+                        format!("<failure>{}</failure>", xml_escape(&details))
+                    } else {
+                        format!(
+                            "<skipped message=\"{} failure\">{}</skipped>",
+                            flags.join(", "),
+                            xml_escape(&details)
+                        )
+                    };
+                    report.push_str(&format!(">\n      {}\n    </testcase>\n", element));
+                }
+            }
+        }
+        report.push_str("  </testsuite>\n");
+    }
+    report.push_str("</testsuites>\n");
+    report
+}
+
+/// A JSON array with an object per case.
+fn json_report(file_outputs: &[FileOutput]) -> String {
+    let objects: Vec<String> = file_outputs
+        .iter()
+        .flat_map(records)
+        .map(|record| {
+            let (kind, expected) = match &record.case.expected_output {
+                ExpectedOutput::Literal(literal) => ("literal", literal.as_str()),
+                ExpectedOutput::Regex { regex, .. } => ("regex", regex.as_str()),
+            };
+            let input: Vec<String> = record
+                .case
+                .input
+                .iter()
+                .map(|line| json_string(line))
+                .collect();
+            format!(
+                concat!(
+                    "  {{\"file\": {}, \"section\": {}, \"line\": {}, \"input\": [{}], ",
+                    "\"expected\": {{\"kind\": \"{}\", \"value\": {}}}, \"actual\": {}, ",
+                    "\"status\": \"{}\", \"soft\": {}, \"deferrable\": {}, \"optional\": {}}}"
+                ),
+                json_string(record.file),
+                json_string(record.section),
+                record.case.line_number,
+                input.join(", "),
+                kind,
+                json_string(expected),
+                record
+                    .output
+                    .actual_output()
+                    .map_or("null".to_owned(), json_string),
+                record.status(),
+                record.case.soft,
+                record.case.deferrable,
+                record.case.optional,
+            )
+        })
+        .collect();
+    if objects.is_empty() {
+        "[]".to_owned()
+    } else {
+        format!("[\n{}\n]", objects.join(",\n"))
+    }
+}
+
+fn json_string(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len() + 2);
+    escaped.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn xml_escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            // Other control characters cannot appear in XML 1.0 at all
+            c if c.is_control() => escaped.push('\u{fffd}'),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn parse_sections(input: &str) -> Vec<Section> {
     let mut lines = input
         .lines()
         .enumerate()
         .filter(|(_, line)| !line.trim().is_empty())
-        .map(|(line_number, line)| (line_number + 1, line.to_owned()));
+        .map(|(line_number, line)| (line_number + 1, line.to_owned()))
+        .peekable();
 
     // meta commands
     let mut deferrable = false;
     let mut soft = false;
     let mut optional = false;
 
+    // Input without an expected output of its own runs as part of the next case
+    let mut setup: Vec<String> = vec![];
+
     let mut sections: Vec<Section> = vec![];
     let mut current_section = Section {
         title: "".to_owned(),
@@ -190,137 +799,48 @@ fn main() {
             }
         } else {
             // Begin a new case
-            let mut input = vec![line];
             let case_line_number = line_number;
-            // Consume all lines until we hit an expected output line
-            for (line_number, line) in lines.by_ref() {
-                if line.starts_with(";") {
-                    // This is an expected output line
-                    let expected_output = if let Some(stripped_line) = line.strip_prefix(";=>") {
-                        // This is a literal expected output
-                        ExpectedOutput::Literal(stripped_line.to_owned())
-                    } else if let Some(stripped_line) = line.strip_prefix(";/") {
-                        // This is a regex expected output
-                        ExpectedOutput::Regex(pcre2::bytes::Regex::new(stripped_line).unwrap())
-                    } else {
-                        panic!(
-                            "Invalid expected output line on line {}: {}",
-                            line_number, line
-                        );
-                    };
-                    // Build the case, push it to the current section, and break from the loop
-                    current_section.cases.push(Case {
-                        input,
-                        expected_output,
-                        line_number: case_line_number,
-                        deferrable,
-                        soft,
-                        optional,
-                    });
-                    break;
+            let mut input = std::mem::take(&mut setup);
+            input.push(line);
+            // Consume input lines until the expected output, which is made up
+            // of `;/` lines for what the input prints and a `;=>` line for its
+            // value
+            let mut expected_lines: Vec<String> = vec![];
+            while let Some((_, line)) = lines.next_if(|(_, line)| {
+                let complete = expected_lines
+                    .last()
+                    .is_some_and(|last| last.starts_with(";=>"));
+                !complete
+                    && (line.starts_with(";=>")
+                        || line.starts_with(";/")
+                        || (expected_lines.is_empty() && !line.starts_with(';')))
+            }) {
+                if line.starts_with(";=>") || line.starts_with(";/") {
+                    expected_lines.push(line);
                 } else {
                     // Push this line to the input
                     input.push(line);
                 }
             }
-        }
-    }
-
-    let env = make_a_lisp_rs::Env::new(None);
-    make_a_lisp_rs::load_builtins(env.borrow_mut().deref_mut());
-
-    let section_outputs = sections
-        .into_iter()
-        .map(|section| SectionOutput {
-            title: section.title,
-            cases: section
-                .cases
-                .into_iter()
-                .map(|case| {
-                    case.run(
-                        CaseRunConfig {
-                            skip_deferrable: false,
-                            skip_soft: false,
-                            skip_optional: false,
-                        },
-                        env.clone(),
-                    )
-                })
-                .collect::<Vec<_>>(),
-        })
-        .collect::<Vec<_>>();
-
-    // Run cases
-    let mut num_cases_seen = 0;
-    let mut passing_cases = 0;
-    let mut soft_fails = 0;
-    let mut required_fails = 0;
-    let mut skipped_cases = 0;
-    for section_output in section_outputs {
-        let num_cases = section_output.cases.len();
-        let failing_cases = section_output
-            .cases
-            .iter()
-            .enumerate()
-            .filter_map(|(i, case)| match case {
-                CaseOutput::Fail(case) => Some((i, case)),
-                _ => None,
-            });
-        let num_skipped_cases = section_output
-            .cases
-            .iter()
-            .filter(|case| matches!(case, CaseOutput::Skip))
-            .count();
-        let num_failing_cases = failing_cases.clone().count();
-        let num_soft_failing_cases = failing_cases
-            .clone()
-            .filter(|(_, case)| case.soft || case.deferrable || case.optional)
-            .count();
-        let num_required_failing_cases = num_failing_cases - num_soft_failing_cases;
-        let num_passing_cases = num_cases - num_failing_cases - num_skipped_cases;
-        if num_failing_cases > 0 {
-            println!("Section {}", section_output.title);
-            for (i, case) in failing_cases {
-                let mut soft_flags = vec![];
-                if case.soft {
-                    soft_flags.push("soft");
-                }
-                if case.deferrable {
-                    soft_flags.push("deferrable");
-                }
-                if case.optional {
-                    soft_flags.push("optional");
-                }
-                let soft_flags = if soft_flags.is_empty() {
-                    "".to_owned()
-                } else {
-                    format!(" ({})", soft_flags.join(", "))
-                };
-                println!(
-                    "Case {} (line {}){}:",
-                    num_cases_seen + i + 1,
-                    case.line_number,
-                    soft_flags
-                );
-                println!("Input> {}", case.input.join("\n"));
-                println!("Expected output {}", case.expected_output);
-                println!("Actual output ;=> {}", case.actual_output);
+            let expected_output =
+                (!expected_lines.is_empty()).then(|| ExpectedOutput::parse(expected_lines));
+            match expected_output {
+                // Build the case and push it to the current section
+                Some(expected_output) => current_section.cases.push(Case {
+                    input,
+                    expected_output,
+                    line_number: case_line_number,
+                    deferrable,
+                    soft,
+                    optional,
+                }),
+                None => setup = input,
             }
-            println!();
         }
-        passing_cases += num_passing_cases;
-        soft_fails += num_soft_failing_cases;
-        required_fails += num_required_failing_cases;
-        num_cases_seen += num_cases;
-        skipped_cases += num_skipped_cases;
     }
-    println!(
-        "Summary: {} cases, {} passed, {} skipped, {} soft fails, {} hard fails, {} total fails",
-        num_cases_seen,
-        passing_cases,
-        skipped_cases,
-        soft_fails,
-        required_fails,
-        required_fails + soft_fails,
-    );
+    if !current_section.cases.is_empty() {
+        sections.push(current_section);
+    }
+
+    sections
 }
@@ -0,0 +1,167 @@
+use std::{fmt::Display, rc::Rc};
+
+use crate::{
+    env::Env,
+    error::MalError,
+    types::{MalData, MalNativeFunction},
+};
+
+/// The kind of value a builtin accepts for a parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MalKind {
+    Any,
+    Integer,
+    String,
+    Symbol,
+    Keyword,
+    Sequence,
+    HashMap,
+    Function,
+    Atom,
+}
+
+impl MalKind {
+    pub fn matches(&self, value: &MalData) -> bool {
+        match self {
+            MalKind::Any => true,
+            MalKind::Integer => matches!(value, MalData::Integer(_)),
+            MalKind::String => matches!(value, MalData::String(_)),
+            MalKind::Symbol => matches!(value, MalData::Symbol(_)),
+            MalKind::Keyword => matches!(value, MalData::Keyword(_)),
+            MalKind::Sequence => matches!(value, MalData::List(..) | MalData::Vector(..)),
+            MalKind::HashMap => matches!(value, MalData::HashMap(..)),
+            MalKind::Function => {
+                matches!(
+                    value,
+                    MalData::MalNativeFunction(..) | MalData::MalClosure(..)
+                )
+            }
+            MalKind::Atom => matches!(value, MalData::Atom(_)),
+        }
+    }
+}
+
+impl Display for MalKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MalKind::Any => write!(f, "any value"),
+            MalKind::Integer => write!(f, "an integer"),
+            MalKind::String => write!(f, "a string"),
+            MalKind::Symbol => write!(f, "a symbol"),
+            MalKind::Keyword => write!(f, "a keyword"),
+            MalKind::Sequence => write!(f, "a list or vector"),
+            MalKind::HashMap => write!(f, "a hash map"),
+            MalKind::Function => write!(f, "a function"),
+            MalKind::Atom => write!(f, "an atom"),
+        }
+    }
+}
+
+/// The number of arguments a builtin accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn contains(&self, count: usize) -> bool {
+        count >= self.min && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+/// Declares the signature of a native function before binding it in an `Env`.
+///
+/// Arguments are checked against the signature before the function is called,
+/// so the function itself only has to handle well-formed input.
+pub struct BuiltinBuilder<'a> {
+    env: &'a mut Env,
+    name: String,
+    params: Vec<MalKind>,
+    optional: Vec<MalKind>,
+    rest: Option<MalKind>,
+}
+
+impl<'a> BuiltinBuilder<'a> {
+    pub(crate) fn new(env: &'a mut Env, name: &str) -> Self {
+        Self {
+            env,
+            name: name.to_owned(),
+            params: vec![],
+            optional: vec![],
+            rest: None,
+        }
+    }
+
+    /// Adds a required parameter.
+    pub fn param(mut self, kind: MalKind) -> Self {
+        self.params.push(kind);
+        self
+    }
+
+    /// Adds an optional parameter following the required ones.
+    pub fn optional(mut self, kind: MalKind) -> Self {
+        self.optional.push(kind);
+        self
+    }
+
+    /// Accepts any number of extra arguments of the given kind.
+    pub fn variadic(mut self, kind: MalKind) -> Self {
+        self.rest = Some(kind);
+        self
+    }
+
+    /// Binds the function under the builder's name.
+    pub fn register<F>(self, function: F)
+    where
+        F: Fn(&[MalData]) -> Result<MalData, MalError> + 'static,
+    {
+        let arity = Arity {
+            min: self.params.len(),
+            max: match self.rest {
+                Some(_) => None,
+                None => Some(self.params.len() + self.optional.len()),
+            },
+        };
+        let name = self.name.clone();
+        let kinds: Vec<MalKind> = self.params.into_iter().chain(self.optional).collect();
+        let rest = self.rest;
+
+        let checked = move |args: &[MalData]| {
+            if !arity.contains(args.len()) {
+                return Err(MalError::WrongBuiltinArity {
+                    name: name.clone(),
+                    arity,
+                    found: args.len(),
+                });
+            }
+            for (i, arg) in args.iter().enumerate() {
+                let kind = kinds.get(i).copied().or(rest).unwrap_or(MalKind::Any);
+                if !kind.matches(arg) {
+                    return Err(MalError::WrongArgumentType {
+                        name: name.clone(),
+                        position: i + 1,
+                        expected: kind,
+                        found: arg.clone(),
+                    });
+                }
+            }
+            function(args)
+        };
+
+        self.env.set(
+            self.name,
+            MalData::MalNativeFunction(MalNativeFunction(Rc::new(Box::new(checked))), None),
+        );
+    }
+}
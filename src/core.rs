@@ -0,0 +1,514 @@
+use std::{cell::RefCell, collections::hash_map, rc::Rc};
+
+use crate::{
+    apply,
+    builtin::MalKind,
+    env::Env,
+    error::MalError,
+    printer::{pr_seq, print_line},
+    read,
+    types::{MalData, MalHashMapKey},
+};
+
+/// Binds the core namespace of native functions in `env`.
+pub fn load(env: &mut Env) {
+    env.builtin("+")
+        .variadic(MalKind::Integer)
+        .register(|args: &[MalData]| {
+            integer_args(args)?
+                .into_iter()
+                .try_fold(0i64, |acc, arg| {
+                    acc.checked_add(arg).ok_or(MalError::IntegerOverflow)
+                })
+                .map(MalData::Integer)
+        });
+
+    env.builtin("-")
+        .param(MalKind::Integer)
+        .variadic(MalKind::Integer)
+        .register(|args: &[MalData]| {
+            let args = integer_args(args)?;
+            if args.len() == 1 {
+                // A single argument is negated
+                return args[0]
+                    .checked_neg()
+                    .ok_or(MalError::IntegerOverflow)
+                    .map(MalData::Integer);
+            }
+            args[1..]
+                .iter()
+                .try_fold(args[0], |acc, arg| {
+                    acc.checked_sub(*arg).ok_or(MalError::IntegerOverflow)
+                })
+                .map(MalData::Integer)
+        });
+
+    env.builtin("*")
+        .variadic(MalKind::Integer)
+        .register(|args: &[MalData]| {
+            integer_args(args)?
+                .into_iter()
+                .try_fold(1i64, |acc, arg| {
+                    acc.checked_mul(arg).ok_or(MalError::IntegerOverflow)
+                })
+                .map(MalData::Integer)
+        });
+
+    env.builtin("/")
+        .param(MalKind::Integer)
+        .variadic(MalKind::Integer)
+        .register(|args: &[MalData]| {
+            let divide = |acc: i64, arg: i64| {
+                if arg == 0 {
+                    Err(MalError::DivisionByZero)
+                } else {
+                    acc.checked_div(arg).ok_or(MalError::IntegerOverflow)
+                }
+            };
+            let args = integer_args(args)?;
+            if args.len() == 1 {
+                // A single argument is the reciprocal
+                return divide(1, args[0]).map(MalData::Integer);
+            }
+            args[1..]
+                .iter()
+                .try_fold(args[0], |acc, arg| divide(acc, *arg))
+                .map(MalData::Integer)
+        });
+
+    env.builtin("read-string")
+        .param(MalKind::String)
+        .register(|args: &[MalData]| read(args[0].as_string()?.to_owned()));
+
+    env.builtin("slurp")
+        .param(MalKind::String)
+        .register(|args: &[MalData]| {
+            Ok(MalData::String(std::fs::read_to_string(
+                args[0].as_string()?,
+            )?))
+        });
+
+    env.builtin("cons")
+        .param(MalKind::Any)
+        .param(MalKind::Sequence)
+        .register(|args: &[MalData]| {
+            let tail = args[1].as_sequence()?;
+            let mut list = Vec::with_capacity(tail.len() + 1);
+            list.push(args[0].clone());
+            list.extend(tail.iter().cloned());
+            Ok(MalData::List(list, None))
+        });
+
+    env.builtin("concat")
+        .variadic(MalKind::Sequence)
+        .register(|args: &[MalData]| {
+            let mut list = Vec::new();
+            for arg in args {
+                list.extend(arg.as_sequence()?.iter().cloned());
+            }
+            Ok(MalData::List(list, None))
+        });
+
+    env.builtin("vec")
+        .param(MalKind::Sequence)
+        .register(|args: &[MalData]| Ok(MalData::Vector(args[0].as_sequence()?.to_vec(), None)));
+
+    env.builtin("nth")
+        .param(MalKind::Sequence)
+        .param(MalKind::Integer)
+        .register(|args: &[MalData]| {
+            let sequence = args[0].as_sequence()?;
+            let index = args[1].as_integer()?;
+            usize::try_from(index)
+                .ok()
+                .and_then(|i| sequence.get(i))
+                .cloned()
+                .ok_or(MalError::IndexOutOfBounds {
+                    index,
+                    length: sequence.len(),
+                })
+        });
+
+    env.builtin("first")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| match &args[0] {
+            MalData::Nil => Ok(MalData::Nil),
+            arg => Ok(arg.as_sequence()?.first().cloned().unwrap_or(MalData::Nil)),
+        });
+
+    env.builtin("rest")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| match &args[0] {
+            MalData::Nil => Ok(MalData::List(vec![], None)),
+            arg => Ok(MalData::List(
+                arg.as_sequence()?.iter().skip(1).cloned().collect(),
+                None,
+            )),
+        });
+
+    env.builtin("seq")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| match &args[0] {
+            MalData::List(sequence, _) | MalData::Vector(sequence, _) if !sequence.is_empty() => {
+                Ok(MalData::List(sequence.clone(), None))
+            }
+            MalData::String(string) if !string.is_empty() => Ok(MalData::List(
+                string
+                    .chars()
+                    .map(|c| MalData::String(c.to_string()))
+                    .collect(),
+                None,
+            )),
+            MalData::List(..) | MalData::Vector(..) | MalData::String(_) | MalData::Nil => {
+                Ok(MalData::Nil)
+            }
+            arg => Err(MalError::TypeError(arg.clone())),
+        });
+
+    env.builtin("conj")
+        .param(MalKind::Sequence)
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| match &args[0] {
+            // Lists grow at the front, vectors at the back
+            MalData::List(list, _) => Ok(MalData::List(
+                args[1..].iter().rev().chain(list).cloned().collect(),
+                None,
+            )),
+            MalData::Vector(vector, _) => Ok(MalData::Vector(
+                vector.iter().chain(&args[1..]).cloned().collect(),
+                None,
+            )),
+            arg => Err(MalError::TypeError(arg.clone())),
+        });
+
+    env.builtin("apply")
+        .param(MalKind::Function)
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| {
+            // The last argument is a sequence spliced after the others
+            let (last, middle) = match args[1..].split_last() {
+                Some((last, middle)) => (last.as_sequence()?, middle),
+                None => (&[][..], &[][..]),
+            };
+            apply(&args[0], middle.iter().chain(last).cloned().collect())
+        });
+
+    env.builtin("map")
+        .param(MalKind::Function)
+        .param(MalKind::Sequence)
+        .register(|args: &[MalData]| {
+            args[1]
+                .as_sequence()?
+                .iter()
+                .map(|element| apply(&args[0], vec![element.clone()]))
+                .collect::<Result<Vec<_>, _>>()
+                .map(|list| MalData::List(list, None))
+        });
+
+    env.builtin("hash-map")
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| {
+            let mut hash_map = hash_map::HashMap::new();
+            insert_pairs(&mut hash_map, args)?;
+            Ok(MalData::HashMap(hash_map, None))
+        });
+
+    env.builtin("assoc")
+        .param(MalKind::HashMap)
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| {
+            let mut hash_map = args[0].as_hash_map()?.clone();
+            insert_pairs(&mut hash_map, &args[1..])?;
+            Ok(MalData::HashMap(hash_map, None))
+        });
+
+    env.builtin("dissoc")
+        .param(MalKind::HashMap)
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| {
+            let mut hash_map = args[0].as_hash_map()?.clone();
+            for key in &args[1..] {
+                hash_map.remove(&MalHashMapKey::try_from(key.clone())?);
+            }
+            Ok(MalData::HashMap(hash_map, None))
+        });
+
+    env.builtin("get")
+        .param(MalKind::Any)
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| match &args[0] {
+            MalData::Nil => Ok(MalData::Nil),
+            arg => {
+                let key = MalHashMapKey::try_from(args[1].clone())?;
+                Ok(arg
+                    .as_hash_map()?
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or(MalData::Nil))
+            }
+        });
+
+    env.builtin("contains?")
+        .param(MalKind::HashMap)
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| {
+            let key = MalHashMapKey::try_from(args[1].clone())?;
+            Ok(MalData::from(args[0].as_hash_map()?.contains_key(&key)))
+        });
+
+    env.builtin("keys")
+        .param(MalKind::HashMap)
+        .register(|args: &[MalData]| {
+            Ok(MalData::List(
+                args[0]
+                    .as_hash_map()?
+                    .keys()
+                    .cloned()
+                    .map(MalData::from)
+                    .collect(),
+                None,
+            ))
+        });
+
+    env.builtin("vals")
+        .param(MalKind::HashMap)
+        .register(|args: &[MalData]| {
+            Ok(MalData::List(
+                args[0].as_hash_map()?.values().cloned().collect(),
+                None,
+            ))
+        });
+
+    env.builtin("pr-str")
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| Ok(MalData::String(pr_seq(args, true, " "))));
+
+    env.builtin("str")
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| Ok(MalData::String(pr_seq(args, false, ""))));
+
+    env.builtin("prn")
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| {
+            print_line(&pr_seq(args, true, " "));
+            Ok(MalData::Nil)
+        });
+
+    env.builtin("println")
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| {
+            print_line(&pr_seq(args, false, " "));
+            Ok(MalData::Nil)
+        });
+
+    env.builtin("throw")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| Err(MalError::Exception(args[0].clone())));
+
+    env.builtin("atom")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| Ok(MalData::Atom(Rc::new(RefCell::new(args[0].clone())))));
+
+    env.builtin("deref")
+        .param(MalKind::Atom)
+        .register(|args: &[MalData]| Ok(args[0].as_atom()?.borrow().clone()));
+
+    env.builtin("reset!")
+        .param(MalKind::Atom)
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| {
+            args[0].as_atom()?.replace(args[1].clone());
+            Ok(args[1].clone())
+        });
+
+    env.builtin("swap!")
+        .param(MalKind::Atom)
+        .param(MalKind::Function)
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| {
+            let atom = args[0].as_atom()?;
+            // Call the function with the current value followed by the extra arguments
+            let mut function_args = Vec::with_capacity(args.len() - 1);
+            function_args.push(atom.borrow().clone());
+            function_args.extend(args[2..].iter().cloned());
+            let value = apply(&args[1], function_args)?;
+            atom.replace(value.clone());
+            Ok(value)
+        });
+
+    env.builtin("with-meta")
+        .param(MalKind::Any)
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| {
+            let meta = Some(Rc::new(args[1].clone()));
+            match args[0].clone() {
+                MalData::List(list, _) => Ok(MalData::List(list, meta)),
+                MalData::Vector(vector, _) => Ok(MalData::Vector(vector, meta)),
+                MalData::HashMap(hash_map, _) => Ok(MalData::HashMap(hash_map, meta)),
+                MalData::MalNativeFunction(f, _) => Ok(MalData::MalNativeFunction(f, meta)),
+                MalData::MalClosure(f, _) => Ok(MalData::MalClosure(f, meta)),
+                value => Err(MalError::TypeError(value)),
+            }
+        });
+
+    env.builtin("meta")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| match &args[0] {
+            MalData::List(_, meta)
+            | MalData::Vector(_, meta)
+            | MalData::HashMap(_, meta)
+            | MalData::MalNativeFunction(_, meta)
+            | MalData::MalClosure(_, meta) => Ok(meta.as_deref().cloned().unwrap_or(MalData::Nil)),
+            arg => Err(MalError::TypeError(arg.clone())),
+        });
+
+    env.builtin("list")
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| Ok(MalData::List(args.to_vec(), None)));
+
+    env.builtin("vector")
+        .variadic(MalKind::Any)
+        .register(|args: &[MalData]| Ok(MalData::Vector(args.to_vec(), None)));
+
+    env.builtin("empty?")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| match &args[0] {
+            MalData::Nil => Ok(MalData::True),
+            MalData::List(sequence, _) | MalData::Vector(sequence, _) => {
+                Ok(MalData::from(sequence.is_empty()))
+            }
+            MalData::HashMap(hash_map, _) => Ok(MalData::from(hash_map.is_empty())),
+            MalData::String(string) => Ok(MalData::from(string.is_empty())),
+            arg => Err(MalError::TypeError(arg.clone())),
+        });
+
+    env.builtin("count")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| {
+            let count = match &args[0] {
+                MalData::Nil => 0,
+                MalData::List(sequence, _) | MalData::Vector(sequence, _) => sequence.len(),
+                MalData::HashMap(hash_map, _) => hash_map.len(),
+                MalData::String(string) => string.chars().count(),
+                arg => return Err(MalError::TypeError(arg.clone())),
+            };
+            Ok(MalData::Integer(count as i64))
+        });
+
+    env.builtin("=")
+        .param(MalKind::Any)
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| Ok(MalData::from(args[0] == args[1])));
+
+    env.builtin("<")
+        .param(MalKind::Integer)
+        .param(MalKind::Integer)
+        .register(|args: &[MalData]| {
+            Ok(MalData::from(args[0].as_integer()? < args[1].as_integer()?))
+        });
+
+    env.builtin("<=")
+        .param(MalKind::Integer)
+        .param(MalKind::Integer)
+        .register(|args: &[MalData]| {
+            Ok(MalData::from(
+                args[0].as_integer()? <= args[1].as_integer()?,
+            ))
+        });
+
+    env.builtin(">")
+        .param(MalKind::Integer)
+        .param(MalKind::Integer)
+        .register(|args: &[MalData]| {
+            Ok(MalData::from(args[0].as_integer()? > args[1].as_integer()?))
+        });
+
+    env.builtin(">=")
+        .param(MalKind::Integer)
+        .param(MalKind::Integer)
+        .register(|args: &[MalData]| {
+            Ok(MalData::from(
+                args[0].as_integer()? >= args[1].as_integer()?,
+            ))
+        });
+
+    env.builtin("not")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| {
+            Ok(MalData::from(matches!(
+                args[0],
+                MalData::Nil | MalData::False
+            )))
+        });
+
+    env.builtin("symbol")
+        .param(MalKind::String)
+        .register(|args: &[MalData]| Ok(MalData::Symbol(args[0].as_string()?.to_owned())));
+
+    env.builtin("keyword")
+        .param(MalKind::Any)
+        .register(|args: &[MalData]| match &args[0] {
+            MalData::String(keyword) | MalData::Keyword(keyword) => {
+                Ok(MalData::Keyword(keyword.clone()))
+            }
+            arg => Err(MalError::TypeError(arg.clone())),
+        });
+
+    env.builtin("time-ms").register(|_: &[MalData]| {
+        let elapsed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(MalData::Integer(elapsed.as_millis() as i64))
+    });
+
+    predicate(env, "list?", |arg| matches!(arg, MalData::List(..)));
+    predicate(env, "vector?", |arg| matches!(arg, MalData::Vector(..)));
+    predicate(env, "map?", |arg| matches!(arg, MalData::HashMap(..)));
+    predicate(env, "sequential?", |arg| {
+        matches!(arg, MalData::List(..) | MalData::Vector(..))
+    });
+    predicate(env, "nil?", |arg| matches!(arg, MalData::Nil));
+    predicate(env, "true?", |arg| matches!(arg, MalData::True));
+    predicate(env, "false?", |arg| matches!(arg, MalData::False));
+    predicate(env, "symbol?", |arg| matches!(arg, MalData::Symbol(_)));
+    predicate(env, "keyword?", |arg| matches!(arg, MalData::Keyword(_)));
+    predicate(env, "string?", |arg| matches!(arg, MalData::String(_)));
+    predicate(env, "number?", |arg| matches!(arg, MalData::Integer(_)));
+    predicate(env, "fn?", |arg| match arg {
+        MalData::MalNativeFunction(..) => true,
+        MalData::MalClosure(closure, _) => !closure.is_macro,
+        _ => false,
+    });
+    predicate(env, "macro?", |arg| match arg {
+        MalData::MalClosure(closure, _) => closure.is_macro,
+        _ => false,
+    });
+    predicate(env, "atom?", |arg| matches!(arg, MalData::Atom(_)));
+}
+
+/// Binds a single-argument builtin returning whether `test` holds.
+fn predicate(env: &mut Env, name: &str, test: fn(&MalData) -> bool) {
+    env.builtin(name)
+        .param(MalKind::Any)
+        .register(move |args: &[MalData]| Ok(MalData::from(test(&args[0]))));
+}
+
+/// Inserts alternating keys and values into `hash_map`.
+fn insert_pairs(
+    hash_map: &mut hash_map::HashMap<MalHashMapKey, MalData>,
+    args: &[MalData],
+) -> Result<(), MalError> {
+    let pairs = args.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(MalError::UnbalancedHashMap { position: None });
+    }
+    for pair in pairs {
+        hash_map.insert(MalHashMapKey::try_from(pair[0].clone())?, pair[1].clone());
+    }
+    Ok(())
+}
+
+/// Collects the arguments of an arithmetic builtin as integers.
+fn integer_args(args: &[MalData]) -> Result<Vec<i64>, MalError> {
+    args.iter().map(MalData::as_integer).collect()
+}
@@ -1,6 +1,14 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    rc::Rc,
+};
 
-use crate::types::MalData;
+use crate::{
+    builtin::{Arity, BuiltinBuilder},
+    error::MalError,
+    types::MalData,
+};
 
 pub struct Env {
     outer: Option<Rc<RefCell<Env>>>,
@@ -15,10 +23,62 @@ impl Env {
         }))
     }
 
+    /// Creates a new environment binding `binds` to `exprs`. A `&` in `binds`
+    /// binds the symbol following it to a list of the remaining `exprs`.
+    pub fn bind(
+        outer: Rc<RefCell<Env>>,
+        binds: &[String],
+        exprs: Vec<MalData>,
+    ) -> Result<Rc<RefCell<Self>>, MalError> {
+        // Parameters before `&` are required and `&` collects any number of the rest
+        let arity = match binds.iter().position(|bind| bind == "&") {
+            Some(required) => Arity {
+                min: required,
+                max: None,
+            },
+            None => Arity {
+                min: binds.len(),
+                max: Some(binds.len()),
+            },
+        };
+        if !arity.contains(exprs.len()) {
+            return Err(MalError::WrongArity {
+                expected: arity,
+                found: exprs.len(),
+            });
+        }
+
+        let env = Self::new(Some(outer));
+        let mut exprs = exprs.into_iter();
+        let mut binds_iter = binds.iter();
+        while let Some(bind) = binds_iter.next() {
+            if bind == "&" {
+                // Variadic parameter
+                let rest = match binds_iter.next() {
+                    Some(rest) => rest.clone(),
+                    None => return Err(MalError::TypeError(MalData::Symbol(bind.clone()))),
+                };
+                env.borrow_mut()
+                    .set(rest, MalData::List(exprs.collect(), None));
+                return Ok(env);
+            }
+            // The arity check guarantees there is an argument for each parameter
+            if let Some(expr) = exprs.next() {
+                env.borrow_mut().set(bind.clone(), expr);
+            }
+        }
+        Ok(env)
+    }
+
     pub fn set(&mut self, key: String, value: MalData) {
         self.data.insert(key, value);
     }
 
+    /// Starts declaring a native function to bind under `name`.
+    pub fn builtin(&mut self, name: &str) -> BuiltinBuilder<'_> {
+        BuiltinBuilder::new(self, name)
+    }
+
     pub fn get(&self, key: &str) -> Option<MalData> {
         if let Some(value) = self.data.get(key) {
             Some(value.clone())
@@ -28,4 +88,19 @@ impl Env {
             None
         }
     }
+
+    /// The symbols bound here or in any outer environment, sorted and without
+    /// duplicates.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys = BTreeSet::new();
+        self.collect_keys(&mut keys);
+        keys.into_iter().collect()
+    }
+
+    fn collect_keys(&self, keys: &mut BTreeSet<String>) {
+        keys.extend(self.data.keys().cloned());
+        if let Some(outer) = &self.outer {
+            outer.borrow().collect_keys(keys);
+        }
+    }
 }
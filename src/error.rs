@@ -1,27 +1,128 @@
+use std::fmt::Display;
+
 use thiserror::Error;
 
-use crate::types::MalData;
+use crate::{
+    builtin::{Arity, MalKind},
+    types::MalData,
+};
+
+/// A 1-based line and column in the reader input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for SourcePosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// Formats the position of errors that can come from both the reader and builtins
+fn at(position: &Option<SourcePosition>) -> String {
+    match position {
+        Some(position) => format!(" at {}", position),
+        None => String::new(),
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum MalError {
-    #[error("unbalanced brackets")]
-    UnbalancedBrackets,
-    #[error("unbalanced double quotes")]
-    UnbalancedDoubleQuotes,
-    #[error("unbalanced hashmap")]
-    UnbalancedHashMap,
-    #[error("expected EOF, found {found:?}")]
-    ExpectedEOF { found: String },
-    #[error("invalid escape sequence")]
-    InvalidEscapeSequence,
-    #[error("invalid token")]
-    InvalidToken,
-    #[error("invalid hash map key")]
-    InvalidHashMapKey,
-    #[error("unexpected {found}")]
-    Unexpected { found: String },
-    #[error("symbol '{0}' not found")]
+    #[error("unbalanced brackets at {position}")]
+    UnbalancedBrackets { position: SourcePosition },
+    #[error("unbalanced double quotes at {position}")]
+    UnbalancedDoubleQuotes {
+        position: SourcePosition,
+        // Whether the string is still open at the end of the input
+        at_end_of_input: bool,
+    },
+    #[error("unbalanced hashmap{}", at(.position))]
+    UnbalancedHashMap { position: Option<SourcePosition> },
+    #[error("expected EOF, found {found:?} at {position}")]
+    ExpectedEOF {
+        found: String,
+        position: SourcePosition,
+    },
+    #[error("invalid escape sequence at {position}")]
+    InvalidEscapeSequence { position: SourcePosition },
+    #[error("invalid token at {position}")]
+    InvalidToken { position: SourcePosition },
+    #[error("invalid hash map key{}", at(.position))]
+    InvalidHashMapKey { position: Option<SourcePosition> },
+    #[error("unexpected {found} at {position}")]
+    Unexpected {
+        found: String,
+        position: SourcePosition,
+    },
+    #[error("'{0}' not found")]
     SymbolNotFound(String),
-    #[error("type error: {0:?}")]
+    #[error("wrong number of arguments: expected {expected}, found {found}")]
+    WrongArity { expected: Arity, found: usize },
+    #[error("wrong number of arguments to '{name}': expected {arity}, found {found}")]
+    WrongBuiltinArity {
+        name: String,
+        arity: Arity,
+        found: usize,
+    },
+    #[error("wrong argument type to '{name}': expected {expected} for argument {position}, found {found}")]
+    WrongArgumentType {
+        name: String,
+        position: usize,
+        expected: MalKind,
+        found: MalData,
+    },
+    #[error("index {index} out of bounds for length {length}")]
+    IndexOutOfBounds { index: i64, length: usize },
+    #[error("integer overflow")]
+    IntegerOverflow,
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("type error: {0}")]
     TypeError(MalData),
+    #[error("exception: {0}")]
+    Exception(MalData),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl MalError {
+    /// The position of the input a reader error was found at.
+    pub fn position(&self) -> Option<SourcePosition> {
+        match self {
+            MalError::UnbalancedBrackets { position }
+            | MalError::UnbalancedDoubleQuotes { position, .. }
+            | MalError::ExpectedEOF { position, .. }
+            | MalError::InvalidEscapeSequence { position }
+            | MalError::InvalidToken { position }
+            | MalError::Unexpected { position, .. } => Some(*position),
+            MalError::UnbalancedHashMap { position } | MalError::InvalidHashMapKey { position } => {
+                *position
+            }
+            _ => None,
+        }
+    }
+
+    /// The value a `catch*` handler sees for this error: thrown values as they
+    /// are, and native errors as their message.
+    pub fn into_value(self) -> MalData {
+        match self {
+            MalError::Exception(value) => value,
+            e => MalData::String(e.to_string()),
+        }
+    }
+
+    /// Whether the reader only failed because the input ended in the middle of
+    /// a form, so that more input could still complete it.
+    pub fn is_incomplete(&self) -> bool {
+        match self {
+            MalError::UnbalancedBrackets { .. } => true,
+            MalError::UnbalancedDoubleQuotes {
+                at_end_of_input, ..
+            } => *at_end_of_input,
+            MalError::Unexpected { found, .. } => found == "EOF",
+            _ => false,
+        }
+    }
 }
@@ -0,0 +1,102 @@
+/// The class of a token produced by [`tokenize`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Brackets and reader macro characters, including `~@`.
+    Special,
+    /// A string literal, which may be missing its closing quote.
+    String,
+    /// A comment running to the end of the line.
+    Comment,
+    /// Anything else: numbers, symbols, keywords, `nil`, `true` and `false`.
+    Atom,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    // Byte offset of the token in the input
+    pub offset: usize,
+}
+
+fn is_special(c: char) -> bool {
+    matches!(
+        c,
+        '[' | ']' | '{' | '}' | '(' | ')' | '\'' | '`' | '~' | '^' | '@'
+    )
+}
+
+/// Characters that end an atom. Note that `~`, `^` and `@` may appear inside an
+/// atom even though they cannot start one.
+fn ends_atom(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(
+            c,
+            '[' | ']' | '{' | '}' | '(' | ')' | '\'' | '"' | '`' | ',' | ';'
+        )
+}
+
+/// Splits the input into tokens in a single pass, skipping whitespace and commas.
+///
+/// Comments are kept so that callers such as a highlighter can see them; the
+/// reader drops them.
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() || c == ',' {
+            chars.next();
+            continue;
+        }
+        chars.next();
+
+        let kind = if is_special(c) {
+            if c == '~' {
+                chars.next_if(|&(_, c)| c == '@');
+            }
+            TokenKind::Special
+        } else if c == '"' {
+            // A backslash escapes any character but a newline, and the string
+            // stops short of a backslash it cannot pair up
+            loop {
+                match chars.peek().map(|&(_, c)| c) {
+                    Some('"') => {
+                        chars.next();
+                        break;
+                    }
+                    Some('\\') => {
+                        let mut lookahead = chars.clone();
+                        lookahead.next();
+                        match lookahead.next() {
+                            Some((_, c)) if c != '\n' => {
+                                chars = lookahead;
+                            }
+                            _ => break,
+                        }
+                    }
+                    Some(_) => {
+                        chars.next();
+                    }
+                    None => break,
+                }
+            }
+            TokenKind::String
+        } else if c == ';' {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            TokenKind::Comment
+        } else {
+            while chars.next_if(|&(_, c)| !ends_atom(c)).is_some() {}
+            TokenKind::Atom
+        };
+
+        let end = chars.peek().map_or(input.len(), |&(i, _)| i);
+        tokens.push(Token {
+            kind,
+            text: input[start..end].to_string(),
+            offset: start,
+        });
+    }
+
+    tokens
+}
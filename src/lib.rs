@@ -1,86 +1,68 @@
-use std::{
-    cell::RefCell,
-    collections::hash_map,
-    rc::Rc,
-};
+use std::{cell::RefCell, collections::hash_map, rc::Rc};
 
+use builtin::MalKind;
 pub use env::Env;
 use error::MalError;
-use types::{MalData, MalNativeFunction};
+pub use printer::capture_output;
+use types::MalData;
 
+pub mod builtin;
+mod core;
 mod env;
 pub mod error;
+pub mod lexer;
+mod printer;
 mod reader;
-mod types;
+pub mod types;
 
-pub fn load_builtins(env: &mut Env) {
-    env.set(
-        "+".to_owned(),
-        MalData::MalNativeFunction(MalNativeFunction(Rc::new(Box::new(
-            |args: &[MalData]| {
-                if let MalData::Integer(arg0) = &args[0] {
-                    if let MalData::Integer(arg1) = &args[1] {
-                        Ok(MalData::Integer(arg0 + arg1))
-                    } else {
-                        Err(MalError::TypeError(args[1].clone()))
-                    }
-                } else {
-                    Err(MalError::TypeError(args[0].clone()))
-                }
-            },
-        )))),
-    );
+/// The special form symbols offered for completion and highlighted in the REPL.
+/// This includes `catch*`, which only has meaning inside a `try*` form.
+pub const SPECIAL_FORMS: &[&str] = &[
+    "def!",
+    "defmacro!",
+    "macroexpand",
+    "let*",
+    "do",
+    "if",
+    "fn*",
+    "quote",
+    "quasiquote",
+    "try*",
+    "catch*",
+];
 
-    env.set(
-        "-".to_owned(),
-        MalData::MalNativeFunction(MalNativeFunction(Rc::new(Box::new(
-            |args: &[MalData]| {
-                if let MalData::Integer(arg0) = &args[0] {
-                    if let MalData::Integer(arg1) = &args[1] {
-                        Ok(MalData::Integer(arg0 - arg1))
-                    } else {
-                        Err(MalError::TypeError(args[1].clone()))
-                    }
-                } else {
-                    Err(MalError::TypeError(args[0].clone()))
-                }
-            },
-        )))),
-    );
+pub fn load_builtins(root_env: Rc<RefCell<Env>>) {
+    let mut env = root_env.borrow_mut();
 
-    env.set(
-        "*".to_owned(),
-        MalData::MalNativeFunction(MalNativeFunction(Rc::new(Box::new(
-            |args: &[MalData]| {
-                if let MalData::Integer(arg0) = &args[0] {
-                    if let MalData::Integer(arg1) = &args[1] {
-                        Ok(MalData::Integer(arg0 * arg1))
-                    } else {
-                        Err(MalError::TypeError(args[1].clone()))
-                    }
-                } else {
-                    Err(MalError::TypeError(args[0].clone()))
-                }
-            },
-        )))),
-    );
+    core::load(&mut env);
 
+    // `eval` and `load-file` always evaluate in the root environment
+    let eval_env = root_env.clone();
+    env.builtin("eval")
+        .param(MalKind::Any)
+        .register(move |args: &[MalData]| eval(args[0].clone(), eval_env.clone()));
+
+    let load_file_env = root_env.clone();
+    env.builtin("load-file")
+        .param(MalKind::String)
+        .register(move |args: &[MalData]| load_file(args[0].as_string()?, load_file_env.clone()));
+
+    env.set("*ARGV*".to_owned(), MalData::List(vec![], None));
     env.set(
-        "/".to_owned(),
-        MalData::MalNativeFunction(MalNativeFunction(Rc::new(Box::new(
-            |args: &[MalData]| {
-                if let MalData::Integer(arg0) = &args[0] {
-                    if let MalData::Integer(arg1) = &args[1] {
-                        Ok(MalData::Integer(arg0 / arg1))
-                    } else {
-                        Err(MalError::TypeError(args[1].clone()))
-                    }
-                } else {
-                    Err(MalError::TypeError(args[0].clone()))
-                }
-            },
-        )))),
+        "*host-language*".to_owned(),
+        MalData::String("rust".to_owned()),
     );
+    drop(env);
+
+    // Builtins that are simplest to write in mal itself
+    rep(
+        "(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) \
+         (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) \
+         (cons 'cond (rest (rest xs)))))))"
+            .to_owned(),
+        root_env,
+    )
+    .expect("the mal prelude should evaluate");
 }
 
 pub fn read(input: String) -> Result<MalData, MalError> {
@@ -88,119 +70,382 @@ pub fn read(input: String) -> Result<MalData, MalError> {
     reader.read_input()
 }
 
-pub fn eval(input: MalData, env: Rc<RefCell<Env>>) -> Result<MalData, MalError> {
-    if let Some(debug_eval) = env.borrow().get("DEBUG-EVAL") {
-        match debug_eval {
-            MalData::Nil | MalData::False => {}
-            _ => println!("EVAL: {}", input),
+pub fn read_all(input: String) -> Result<Vec<MalData>, MalError> {
+    let mut reader = reader::Reader::new(input);
+    reader.read_all()
+}
+
+/// Reads and evaluates every form in the file at `path`, returning nil.
+pub fn load_file(path: &str, env: Rc<RefCell<Env>>) -> Result<MalData, MalError> {
+    let contents = std::fs::read_to_string(path)?;
+    for ast in read_all(contents)? {
+        eval(ast, env.clone())?;
+    }
+    Ok(MalData::Nil)
+}
+
+/// Expands a quasiquoted form into the equivalent `cons`/`concat` calls.
+fn quasiquote(ast: &MalData) -> MalData {
+    match ast {
+        MalData::List(list, _) => {
+            if let [MalData::Symbol(s), unquoted] = list.as_slice() {
+                if s == "unquote" {
+                    return unquoted.clone();
+                }
+            }
+            quasiquote_sequence(list)
+        }
+        MalData::Vector(vector, _) => MalData::List(
+            vec![
+                MalData::Symbol("vec".to_owned()),
+                quasiquote_sequence(vector),
+            ],
+            None,
+        ),
+        MalData::Symbol(_) | MalData::HashMap(_, _) => {
+            MalData::List(vec![MalData::Symbol("quote".to_owned()), ast.clone()], None)
+        }
+        _ => ast.clone(),
+    }
+}
+
+fn quasiquote_sequence(sequence: &[MalData]) -> MalData {
+    // Build the result back to front, splicing in `splice-unquote` forms
+    let mut result = MalData::List(vec![], None);
+    for element in sequence.iter().rev() {
+        if let MalData::List(list, _) = element {
+            if let [MalData::Symbol(s), spliced] = list.as_slice() {
+                if s == "splice-unquote" {
+                    result = MalData::List(
+                        vec![
+                            MalData::Symbol("concat".to_owned()),
+                            spliced.clone(),
+                            result,
+                        ],
+                        None,
+                    );
+                    continue;
+                }
+            }
+        }
+        result = MalData::List(
+            vec![
+                MalData::Symbol("cons".to_owned()),
+                quasiquote(element),
+                result,
+            ],
+            None,
+        );
+    }
+    result
+}
+
+/// Calls a native function or closure with already evaluated arguments.
+pub fn apply(function: &MalData, args: Vec<MalData>) -> Result<MalData, MalError> {
+    match function {
+        MalData::MalNativeFunction(f, _) => f.0.as_ref()(&args),
+        MalData::MalClosure(f, _) => eval(
+            f.body.as_ref().clone(),
+            Env::bind(f.env.clone(), &f.params, args)?,
+        ),
+        _ => Err(MalError::TypeError(function.clone())),
+    }
+}
+
+/// Returns the macro and its unevaluated arguments if `ast` is a macro call.
+fn macro_call(ast: &MalData, env: &Rc<RefCell<Env>>) -> Option<(MalData, Vec<MalData>)> {
+    if let MalData::List(list, _) = ast {
+        if let Some(MalData::Symbol(s)) = list.first() {
+            if let Some(MalData::MalClosure(closure, _)) = env.borrow().get(s) {
+                if closure.is_macro {
+                    return Some((MalData::MalClosure(closure, None), list[1..].to_vec()));
+                }
+            }
         }
     }
+    None
+}
+
+/// Repeatedly expands `ast` until it is no longer a macro call.
+fn macroexpand(mut ast: MalData, env: &Rc<RefCell<Env>>) -> Result<MalData, MalError> {
+    while let Some((macro_function, args)) = macro_call(&ast, env) {
+        ast = apply(&macro_function, args)?;
+    }
+    Ok(ast)
+}
+
+pub fn eval(mut input: MalData, mut env: Rc<RefCell<Env>>) -> Result<MalData, MalError> {
     use MalData::*;
-    match &input {
-        Symbol(s) => {
-            if let Some(value) = env.borrow().get(s) {
-                Ok(value.clone())
-            } else {
-                Err(MalError::SymbolNotFound(s.clone()))
+    // Forms in tail position replace `input` and `env` and loop instead of recursing
+    loop {
+        if let Some(debug_eval) = env.borrow().get("DEBUG-EVAL") {
+            match debug_eval {
+                MalData::Nil | MalData::False => {}
+                _ => printer::print_line(&format!("EVAL: {}", input)),
             }
         }
-        List(list) => {
-            if !list.is_empty() {
-                if let MalData::Symbol(s) = &list[0] {
-                    match s.as_str() {
-                        "def!" => {
-                            // Check if the list is the right length
-                            if list.len() != 3 {
-                                return Err(MalError::TypeError(input.clone()));
+        // Expand macro calls before evaluating them
+        input = macroexpand(input, &env)?;
+        return match &input {
+            Symbol(s) => {
+                if let Some(value) = env.borrow().get(s) {
+                    Ok(value.clone())
+                } else {
+                    Err(MalError::SymbolNotFound(s.clone()))
+                }
+            }
+            List(list, _) => {
+                if !list.is_empty() {
+                    if let MalData::Symbol(s) = &list[0] {
+                        match s.as_str() {
+                            "def!" => {
+                                // Check if the list is the right length
+                                if list.len() != 3 {
+                                    return Err(MalError::TypeError(input.clone()));
+                                }
+                                // Get the key and value
+                                if let MalData::Symbol(key) = &list[1] {
+                                    let value = eval(list[2].clone(), env.clone())?;
+                                    env.borrow_mut().set(key.clone(), value.clone());
+                                    return Ok(value);
+                                } else {
+                                    // Error
+                                    return Err(MalError::TypeError(list[1].clone()));
+                                }
                             }
-                            // Get the key and value
-                            if let MalData::Symbol(key) = &list[1] {
-                                let value = eval(list[2].clone(), env.clone())?;
-                                env.borrow_mut().set(key.clone(), value.clone());
-                                return Ok(value);
-                            } else {
-                                // Error
-                                return Err(MalError::TypeError(list[1].clone()));
+                            "defmacro!" => {
+                                // Check if the list is the right length
+                                if list.len() != 3 {
+                                    return Err(MalError::TypeError(input.clone()));
+                                }
+                                // Get the key and the closure to turn into a macro
+                                if let MalData::Symbol(key) = &list[1] {
+                                    let value = match eval(list[2].clone(), env.clone())? {
+                                        MalData::MalClosure(closure, meta) => MalData::MalClosure(
+                                            types::MalClosure {
+                                                is_macro: true,
+                                                ..closure
+                                            },
+                                            meta,
+                                        ),
+                                        value => return Err(MalError::TypeError(value)),
+                                    };
+                                    env.borrow_mut().set(key.clone(), value.clone());
+                                    return Ok(value);
+                                } else {
+                                    // Error
+                                    return Err(MalError::TypeError(list[1].clone()));
+                                }
                             }
-                        }
-                        "let*" => {
-                            // Check if the list has an even number of elements
-                            if list.len() != 3 {
-                                return Err(MalError::TypeError(input.clone()));
+                            "macroexpand" => {
+                                // Check if the list has exactly one argument
+                                if list.len() != 2 {
+                                    return Err(MalError::TypeError(input.clone()));
+                                }
+                                return macroexpand(list[1].clone(), &env);
                             }
-                            // Evaluate the bindings list
-                            if let MalData::List(bindings) | MalData::Vector(bindings) = &list[1] {
-                                let pairs = bindings.chunks_exact(2);
-                                if !pairs.remainder().is_empty() {
+                            "let*" => {
+                                // Check if the list has an even number of elements
+                                if list.len() != 3 {
+                                    return Err(MalError::TypeError(input.clone()));
+                                }
+                                // Evaluate the bindings list
+                                if let MalData::List(bindings, _) | MalData::Vector(bindings, _) =
+                                    &list[1]
+                                {
+                                    let pairs = bindings.chunks_exact(2);
+                                    if !pairs.remainder().is_empty() {
+                                        return Err(MalError::TypeError(list[1].clone()));
+                                    }
+                                    let new_env = Env::new(Some(env.clone()));
+                                    // Load up the new environment with new bindings
+                                    for pair in pairs {
+                                        let key = if let MalData::Symbol(key) = &pair[0] {
+                                            key.clone()
+                                        } else {
+                                            return Err(MalError::TypeError(pair[0].clone()));
+                                        };
+                                        let value = eval(pair[1].clone(), new_env.clone())?;
+                                        new_env.borrow_mut().set(key, value);
+                                    }
+                                    // Evaluate the body in tail position
+                                    input = list[2].clone();
+                                    env = new_env;
+                                    continue;
+                                } else {
+                                    // Error
                                     return Err(MalError::TypeError(list[1].clone()));
                                 }
-                                let new_env = Env::new(Some(env.clone()));
-                                // Load up the new environment with new bindings
-                                for pair in pairs {
-                                    let key = if let MalData::Symbol(key) = &pair[0] {
-                                        key.clone()
-                                    } else {
-                                        return Err(MalError::TypeError(pair[0].clone()));
-                                    };
-                                    let value = eval(pair[1].clone(), new_env.clone())?;
-                                    new_env.borrow_mut().set(key, value);
-                                }
-                                // Evaluate the body
-                                return eval(list[2].clone(), new_env);
-                            } else {
-                                // Error
-                                return Err(MalError::TypeError(list[1].clone()));
                             }
+                            "do" => {
+                                // Evaluate all forms but the last, which is in tail position
+                                if list.len() == 1 {
+                                    return Ok(MalData::Nil);
+                                }
+                                for form in list[1..list.len() - 1].iter() {
+                                    eval(form.clone(), env.clone())?;
+                                }
+                                input = list[list.len() - 1].clone();
+                                continue;
+                            }
+                            "if" => {
+                                // Check if the list has a condition and one or two branches
+                                if list.len() != 3 && list.len() != 4 {
+                                    return Err(MalError::TypeError(input.clone()));
+                                }
+                                let condition = eval(list[1].clone(), env.clone())?;
+                                // Evaluate the chosen branch in tail position
+                                input = match condition {
+                                    MalData::Nil | MalData::False => match list.get(3) {
+                                        Some(else_branch) => else_branch.clone(),
+                                        None => return Ok(MalData::Nil),
+                                    },
+                                    _ => list[2].clone(),
+                                };
+                                continue;
+                            }
+                            "quote" => {
+                                // Check if the list has exactly one argument
+                                if list.len() != 2 {
+                                    return Err(MalError::TypeError(input.clone()));
+                                }
+                                return Ok(list[1].clone());
+                            }
+                            "quasiquote" => {
+                                // Check if the list has exactly one argument
+                                if list.len() != 2 {
+                                    return Err(MalError::TypeError(input.clone()));
+                                }
+                                // Evaluate the expansion in tail position
+                                input = quasiquote(&list[1]);
+                                continue;
+                            }
+                            "try*" => {
+                                // Without a catch* form the body is in tail position
+                                if list.len() == 2 {
+                                    input = list[1].clone();
+                                    continue;
+                                }
+                                // Check if the list has a body and a catch* form
+                                if list.len() != 3 {
+                                    return Err(MalError::TypeError(input.clone()));
+                                }
+                                let (binding, handler) = match &list[2] {
+                                    MalData::List(catch, _) => match catch.as_slice() {
+                                        [MalData::Symbol(catch), MalData::Symbol(binding), handler]
+                                            if catch == "catch*" =>
+                                        {
+                                            (binding.clone(), handler.clone())
+                                        }
+                                        _ => return Err(MalError::TypeError(list[2].clone())),
+                                    },
+                                    _ => return Err(MalError::TypeError(list[2].clone())),
+                                };
+                                match eval(list[1].clone(), env.clone()) {
+                                    Ok(value) => return Ok(value),
+                                    Err(e) => {
+                                        let value = e.into_value();
+                                        // Evaluate the handler in tail position
+                                        env = Env::bind(env.clone(), &[binding], vec![value])?;
+                                        input = handler;
+                                        continue;
+                                    }
+                                }
+                            }
+                            "fn*" => {
+                                // Check if the list has a parameter list and a body
+                                if list.len() != 3 {
+                                    return Err(MalError::TypeError(input.clone()));
+                                }
+                                if let MalData::List(params, _) | MalData::Vector(params, _) =
+                                    &list[1]
+                                {
+                                    let params = params
+                                        .iter()
+                                        .map(|param| {
+                                            if let MalData::Symbol(param) = param {
+                                                Ok(param.clone())
+                                            } else {
+                                                Err(MalError::TypeError(param.clone()))
+                                            }
+                                        })
+                                        .collect::<Result<Vec<_>, _>>()?;
+                                    return Ok(MalData::MalClosure(
+                                        types::MalClosure {
+                                            params,
+                                            body: Rc::new(list[2].clone()),
+                                            env: env.clone(),
+                                            is_macro: false,
+                                        },
+                                        None,
+                                    ));
+                                } else {
+                                    // Error
+                                    return Err(MalError::TypeError(list[1].clone()));
+                                }
+                            }
+                            _ => {}
                         }
-                        _ => {}
                     }
                 }
-            }
 
-            // "Apply phase"
-            let evaluated_list = list
-                .iter()
-                .map(|el| eval(el.clone(), env.clone()))
-                .collect::<Result<Vec<_>, _>>()?;
-            if !evaluated_list.is_empty() {
-                if let MalData::MalNativeFunction(f) = &evaluated_list[0] {
-                    // Apply the function
-                    Ok(f.0.as_ref()(&evaluated_list[1..])?)
+                // "Apply phase"
+                let evaluated_list = list
+                    .iter()
+                    .map(|el| eval(el.clone(), env.clone()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if !evaluated_list.is_empty() {
+                    match &evaluated_list[0] {
+                        // Apply the function
+                        MalData::MalNativeFunction(f, _) => Ok(f.0.as_ref()(&evaluated_list[1..])?),
+                        MalData::MalClosure(f, _) => {
+                            // Evaluate the closure body in tail position
+                            env =
+                                Env::bind(f.env.clone(), &f.params, evaluated_list[1..].to_vec())?;
+                            input = f.body.as_ref().clone();
+                            continue;
+                        }
+                        _ => Err(MalError::TypeError(evaluated_list[0].clone())),
+                    }
                 } else {
-                    Err(MalError::TypeError(evaluated_list[0].clone()))
+                    Ok(MalData::List(evaluated_list, None))
                 }
-            } else {
-                Ok(MalData::List(evaluated_list))
             }
-        }
-        Vector(vector) => Ok(MalData::Vector(
-            vector
-                .iter()
-                .map(|el| eval(el.clone(), env.clone()))
-                .collect::<Result<Vec<_>, _>>()?,
-        )),
-        HashMap(hash_map) => {
-            // Evaluate the hashmap
-            let evaluated_hash_map: Result<
-                hash_map::HashMap<types::MalHashMapKey, MalData>,
-                MalError,
-            > = hash_map
-                .iter()
-                .map(|(key, value)| {
-                    eval(value.clone(), env.clone()).map(|value| (key.clone(), value))
-                })
-                .collect();
-            Ok(MalData::HashMap(evaluated_hash_map?))
-        }
-        _ => Ok(input),
+            Vector(vector, _) => Ok(MalData::Vector(
+                vector
+                    .iter()
+                    .map(|el| eval(el.clone(), env.clone()))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None,
+            )),
+            HashMap(hash_map, _) => {
+                // Evaluate the hashmap
+                let evaluated_hash_map: Result<
+                    hash_map::HashMap<types::MalHashMapKey, MalData>,
+                    MalError,
+                > = hash_map
+                    .iter()
+                    .map(|(key, value)| {
+                        eval(value.clone(), env.clone()).map(|value| (key.clone(), value))
+                    })
+                    .collect();
+                Ok(MalData::HashMap(evaluated_hash_map?, None))
+            }
+            _ => Ok(input),
+        };
     }
 }
 
 pub fn print(input: MalData) -> String {
-    format!("{}", input)
+    printer::pr_str(&input, true)
+}
+
+/// Reads and evaluates the input, returning the value rather than its printed
+/// form.
+pub fn re(input: String, env: Rc<RefCell<Env>>) -> Result<MalData, MalError> {
+    read(input).and_then(|ast| eval(ast, env))
 }
 
 pub fn rep(input: String, env: Rc<RefCell<Env>>) -> Result<String, MalError> {
-    read(input)
-        .and_then(|ast| eval(ast, env)).map(print)
+    re(input, env).map(print)
 }
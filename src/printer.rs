@@ -0,0 +1,119 @@
+use std::cell::RefCell;
+
+use crate::types::{MalData, MalHashMapKey};
+
+thread_local! {
+    // Output collected by `capture_output` instead of going to stdout
+    static CAPTURED_OUTPUT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Writes a line of program output to stdout, or to the buffer of an enclosing
+/// [`capture_output`].
+pub fn print_line(line: &str) {
+    CAPTURED_OUTPUT.with(|captured| match captured.borrow_mut().as_mut() {
+        Some(output) => {
+            output.push_str(line);
+            output.push('\n');
+        }
+        None => println!("{}", line),
+    });
+}
+
+/// Runs `f`, collecting what the program prints with `prn`, `println` and
+/// `DEBUG-EVAL` rather than writing it to stdout.
+pub fn capture_output<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let outer = CAPTURED_OUTPUT.with(|captured| captured.replace(Some(String::new())));
+    let result = f();
+    let output = CAPTURED_OUTPUT.with(|captured| captured.replace(outer));
+    (result, output.unwrap_or_default())
+}
+
+/// Prints `data` as a string. With `print_readably`, strings are quoted and
+/// escaped so that the output can be read back in.
+pub fn pr_str(data: &MalData, print_readably: bool) -> String {
+    let mut output = String::new();
+    write_data(&mut output, data, print_readably);
+    output
+}
+
+/// Prints each of `values` and joins them with `separator`.
+pub fn pr_seq(values: &[MalData], print_readably: bool, separator: &str) -> String {
+    let mut output = String::new();
+    write_seq(&mut output, values, print_readably, separator);
+    output
+}
+
+fn write_seq(output: &mut String, values: &[MalData], print_readably: bool, separator: &str) {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            output.push_str(separator);
+        }
+        write_data(output, value, print_readably);
+    }
+}
+
+fn write_data(output: &mut String, data: &MalData, print_readably: bool) {
+    match data {
+        MalData::List(list, _) => {
+            output.push('(');
+            write_seq(output, list, print_readably, " ");
+            output.push(')');
+        }
+        MalData::Vector(vector, _) => {
+            output.push('[');
+            write_seq(output, vector, print_readably, " ");
+            output.push(']');
+        }
+        MalData::HashMap(hash_map, _) => {
+            output.push('{');
+            for (i, (key, value)) in hash_map.iter().enumerate() {
+                if i > 0 {
+                    output.push(' ');
+                }
+                match key {
+                    MalHashMapKey::String(string) => write_string(output, string, print_readably),
+                    MalHashMapKey::Keyword(keyword) => {
+                        output.push(':');
+                        output.push_str(keyword);
+                    }
+                }
+                output.push(' ');
+                write_data(output, value, print_readably);
+            }
+            output.push('}');
+        }
+        MalData::Integer(integer) => output.push_str(&integer.to_string()),
+        MalData::Symbol(symbol) => output.push_str(symbol),
+        MalData::Nil => output.push_str("nil"),
+        MalData::True => output.push_str("true"),
+        MalData::False => output.push_str("false"),
+        MalData::String(string) => write_string(output, string, print_readably),
+        MalData::Keyword(keyword) => {
+            output.push(':');
+            output.push_str(keyword);
+        }
+        MalData::MalNativeFunction(_, _) | MalData::MalClosure(_, _) => output.push_str("<fn>"),
+        MalData::Atom(atom) => {
+            output.push_str("(atom ");
+            write_data(output, &atom.borrow(), print_readably);
+            output.push(')');
+        }
+    }
+}
+
+fn write_string(output: &mut String, string: &str, print_readably: bool) {
+    if !print_readably {
+        output.push_str(string);
+        return;
+    }
+    output.push('"');
+    for c in string.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '"' => output.push_str("\\\""),
+            '\n' => output.push_str("\\n"),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+}
@@ -1,31 +1,46 @@
 use std::collections::hash_map;
 
 use crate::{
-    error::MalError,
+    error::{MalError, SourcePosition},
+    lexer::{tokenize, Token, TokenKind},
     types::{MalData, MalHashMapKey},
 };
 
 pub struct Reader {
-    tokens: Vec<String>,
+    input: String,
+    tokens: Vec<Token>,
     i: usize,
 }
 
 impl Reader {
     pub fn new(input: String) -> Self {
-        let tokens = Self::tokenize(input);
-        Self { tokens, i: 0 }
+        let tokens = tokenize(&input)
+            .into_iter()
+            .filter(|token| token.kind != TokenKind::Comment)
+            .collect();
+        Self {
+            input,
+            tokens,
+            i: 0,
+        }
+    }
+
+    /// Converts a byte offset in the input to a line and column.
+    fn position(&self, offset: usize) -> SourcePosition {
+        let before = &self.input[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        SourcePosition {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
     }
 
-    // TODO: benchmark this and implement a better tokenizer than regex
-    fn tokenize(input: String) -> Vec<String> {
-        let re = regex::Regex::new(
-            r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"#,
-        )
-        .unwrap();
-        re.captures_iter(input.as_str())
-            .map(|cap| cap[1].to_string())
-            .filter(|token| !token.starts_with(";"))
-            .collect()
+    /// The position of the current token, or of the end of the input.
+    fn current_position(&self) -> SourcePosition {
+        match self.tokens.get(self.i) {
+            Some(token) => self.position(token.offset),
+            None => self.position(self.input.len()),
+        }
     }
 
     pub fn read_input(&mut self) -> Result<MalData, MalError> {
@@ -35,84 +50,107 @@ impl Reader {
         let data = self.read_form()?;
         if self.i < self.tokens.len() {
             return Err(MalError::ExpectedEOF {
-                found: self.tokens[self.i].clone(),
+                found: self.tokens[self.i].text.clone(),
+                position: self.current_position(),
             });
         }
         Ok(data)
     }
 
+    /// Reads every form in the input.
+    pub fn read_all(&mut self) -> Result<Vec<MalData>, MalError> {
+        let mut forms = Vec::new();
+        while self.i < self.tokens.len() {
+            forms.push(self.read_form()?);
+        }
+        Ok(forms)
+    }
+
     fn read_form(&mut self) -> Result<MalData, MalError> {
         if self.i >= self.tokens.len() {
             return Err(MalError::Unexpected {
                 found: "EOF".to_string(),
+                position: self.current_position(),
             });
         }
-        if self.tokens[self.i] == "(" {
+        if matches!(self.tokens[self.i].text.as_str(), ")" | "]" | "}") {
+            // A closing bracket that does not end any open sequence
+            return Err(MalError::Unexpected {
+                found: self.tokens[self.i].text.clone(),
+                position: self.current_position(),
+            });
+        }
+        if self.tokens[self.i].text == "(" {
             self.i += 1;
             self.read_list()
-        } else if self.tokens[self.i] == "[" {
+        } else if self.tokens[self.i].text == "[" {
             self.i += 1;
             self.read_vector()
-        } else if self.tokens[self.i] == "{" {
+        } else if self.tokens[self.i].text == "{" {
             self.i += 1;
             self.read_hash_map()
-        } else if let Some(stripped_token) = self.tokens[self.i].strip_prefix(":") {
+        } else if let Some(stripped_token) = self.tokens[self.i].text.strip_prefix(":") {
             // keyword
             self.i += 1;
             if stripped_token.is_empty() {
-                return Err(MalError::InvalidToken);
+                return Err(MalError::InvalidToken {
+                    position: self.position(self.tokens[self.i - 1].offset),
+                });
             }
             Ok(MalData::Keyword(stripped_token.to_string()))
-        } else if self.tokens[self.i] == "'" {
+        } else if self.tokens[self.i].text == "'" {
             // quotes reader macro
             self.i += 1;
             let parsed = self.read_form()?;
-            Ok(MalData::List(vec![
-                MalData::Symbol("quote".to_string()),
-                parsed,
-            ]))
-        } else if self.tokens[self.i].starts_with("`") {
+            Ok(MalData::List(
+                vec![MalData::Symbol("quote".to_string()), parsed],
+                None,
+            ))
+        } else if self.tokens[self.i].text.starts_with("`") {
             // quasiquote reader macro
             self.i += 1;
             let parsed = self.read_form()?;
-            Ok(MalData::List(vec![
-                MalData::Symbol("quasiquote".to_string()),
-                parsed,
-            ]))
-        } else if self.tokens[self.i].starts_with("~@") {
+            Ok(MalData::List(
+                vec![MalData::Symbol("quasiquote".to_string()), parsed],
+                None,
+            ))
+        } else if self.tokens[self.i].text.starts_with("~@") {
             // splice-unquote reader macro
             self.i += 1;
             let parsed = self.read_form()?;
-            Ok(MalData::List(vec![
-                MalData::Symbol("splice-unquote".to_string()),
-                parsed,
-            ]))
-        } else if self.tokens[self.i].starts_with("~") {
+            Ok(MalData::List(
+                vec![MalData::Symbol("splice-unquote".to_string()), parsed],
+                None,
+            ))
+        } else if self.tokens[self.i].text.starts_with("~") {
             // unquote reader macro
             self.i += 1;
             let parsed = self.read_form()?;
-            Ok(MalData::List(vec![
-                MalData::Symbol("unquote".to_string()),
-                parsed,
-            ]))
-        } else if self.tokens[self.i].starts_with("@") {
+            Ok(MalData::List(
+                vec![MalData::Symbol("unquote".to_string()), parsed],
+                None,
+            ))
+        } else if self.tokens[self.i].text.starts_with("@") {
             // deref reader macro
             self.i += 1;
             let parsed = self.read_form()?;
-            Ok(MalData::List(vec![
-                MalData::Symbol("deref".to_string()),
-                parsed,
-            ]))
-        } else if self.tokens[self.i].starts_with("^") {
+            Ok(MalData::List(
+                vec![MalData::Symbol("deref".to_string()), parsed],
+                None,
+            ))
+        } else if self.tokens[self.i].text.starts_with("^") {
             // deref reader macro
             self.i += 1;
             let parsed_meta = self.read_form()?;
             let parsed = self.read_form()?;
-            Ok(MalData::List(vec![
-                MalData::Symbol("with-meta".to_string()),
-                parsed,
-                parsed_meta,
-            ]))
+            Ok(MalData::List(
+                vec![
+                    MalData::Symbol("with-meta".to_string()),
+                    parsed,
+                    parsed_meta,
+                ],
+                None,
+            ))
         } else {
             self.read_atom()
         }
@@ -120,43 +158,64 @@ impl Reader {
 
     fn read_list(&mut self) -> Result<MalData, MalError> {
         self.read_sequence(")")
-            .map(MalData::List)
+            .map(|list| MalData::List(list, None))
     }
 
     fn read_vector(&mut self) -> Result<MalData, MalError> {
         self.read_sequence("]")
-            .map(MalData::Vector)
+            .map(|vector| MalData::Vector(vector, None))
     }
 
     fn read_hash_map(&mut self) -> Result<MalData, MalError> {
-        let sequence = self.read_sequence("}")?;
+        let opening_offset = self.tokens[self.i - 1].offset;
+        let sequence = self.read_sequence_with_offsets("}")?;
 
         let kv_pairs = sequence.chunks_exact(2);
         if !kv_pairs.remainder().is_empty() {
-            return Err(MalError::UnbalancedHashMap);
+            return Err(MalError::UnbalancedHashMap {
+                position: Some(self.position(opening_offset)),
+            });
         }
 
         let mut hash_map = hash_map::HashMap::new();
 
         for kv_pair in kv_pairs {
-            let key = MalHashMapKey::try_from(kv_pair[0].clone())?;
-            hash_map.insert(key, kv_pair[1].clone());
+            let (key_offset, key) = &kv_pair[0];
+            let key =
+                MalHashMapKey::try_from(key.clone()).map_err(|_| MalError::InvalidHashMapKey {
+                    position: Some(self.position(*key_offset)),
+                })?;
+            hash_map.insert(key, kv_pair[1].1.clone());
         }
 
-        Ok(MalData::HashMap(hash_map))
+        Ok(MalData::HashMap(hash_map, None))
     }
 
     fn read_sequence(&mut self, ending_token: &str) -> Result<Vec<MalData>, MalError> {
+        self.read_sequence_with_offsets(ending_token)
+            .map(|sequence| sequence.into_iter().map(|(_, form)| form).collect())
+    }
+
+    /// Reads the forms up to `ending_token`, each with the offset of its first
+    /// token.
+    fn read_sequence_with_offsets(
+        &mut self,
+        ending_token: &str,
+    ) -> Result<Vec<(usize, MalData)>, MalError> {
+        // Unbalanced brackets are reported at the opening bracket
+        let opening_offset = self.tokens[self.i - 1].offset;
         let mut sequence = Vec::new();
         loop {
             if self.i >= self.tokens.len() {
-                return Err(MalError::UnbalancedBrackets);
+                return Err(MalError::UnbalancedBrackets {
+                    position: self.position(opening_offset),
+                });
             }
-            let token = self.tokens[self.i].clone();
-            if token == ending_token {
+            if self.tokens[self.i].text == ending_token {
                 break;
             } else {
-                sequence.push(self.read_form()?);
+                let offset = self.tokens[self.i].offset;
+                sequence.push((offset, self.read_form()?));
             }
         }
         self.i += 1;
@@ -164,13 +223,13 @@ impl Reader {
     }
 
     fn read_atom(&mut self) -> Result<MalData, MalError> {
-        let token = self.tokens[self.i].clone();
+        let token = self.tokens[self.i].text.clone();
         let parsed = match token.parse::<i64>() {
             Ok(integer) => MalData::Integer(integer),
             Err(_) => {
                 if token.starts_with("\"") {
                     // Parse escape sequences
-                    parse_string(token)?
+                    self.parse_string(&self.tokens[self.i])?
                 } else {
                     match token.as_str() {
                         "nil" => MalData::Nil,
@@ -184,59 +243,73 @@ impl Reader {
         self.i += 1;
         Ok(parsed)
     }
-}
 
-// Parse a string token
-fn parse_string(token: String) -> Result<MalData, MalError> {
-    if !token.starts_with('"') {
-        panic!("Expected string token to start with '\"'");
-    }
+    // Parse a string token
+    fn parse_string(&self, token: &Token) -> Result<MalData, MalError> {
+        if !token.text.starts_with('"') {
+            panic!("Expected string token to start with '\"'");
+        }
 
-    // Start parsing the string
-    let mut i = 1;
-    let mut parsed_string = String::new();
-    let chars: Vec<_> = token.chars().collect();
-    let token_len = chars.len();
-
-    while i < token_len {
-        // Handle escape sequences
-        if chars[i] == '\\' {
-            // Check if the next character is a valid escape sequence
-            if i + 1 >= token_len {
-                return Err(MalError::UnbalancedDoubleQuotes);
-            }
+        // Start parsing the string
+        let mut i = 1;
+        let mut parsed_string = String::new();
+        let chars: Vec<_> = token.text.char_indices().collect();
+        let token_len = chars.len();
+        let position_at = |i: usize| self.position(token.offset + chars[i].0);
+        // Only a string running into the end of the input could be closed by more input
+        let at_end_of_input = token.offset + token.text.len() == self.input.len();
 
-            if chars[i + 1] == 'n' {
-                // Newline escape sequence
-                parsed_string.push('\n');
-                i += 2;
-            } else if chars[i + 1] == '\\' {
-                // Backslash escape sequence
-                parsed_string.push('\\');
-                i += 2;
-            } else if chars[i + 1] == '"' {
-                // Double quote escape sequence
-                parsed_string.push('"');
-                i += 2;
-            } else {
-                return Err(MalError::InvalidEscapeSequence);
+        while i < token_len {
+            // Handle escape sequences
+            if chars[i].1 == '\\' {
+                // Check if the next character is a valid escape sequence
+                if i + 1 >= token_len {
+                    return Err(MalError::UnbalancedDoubleQuotes {
+                        position: position_at(0),
+                        at_end_of_input,
+                    });
+                }
+
+                if chars[i + 1].1 == 'n' {
+                    // Newline escape sequence
+                    parsed_string.push('\n');
+                    i += 2;
+                } else if chars[i + 1].1 == '\\' {
+                    // Backslash escape sequence
+                    parsed_string.push('\\');
+                    i += 2;
+                } else if chars[i + 1].1 == '"' {
+                    // Double quote escape sequence
+                    parsed_string.push('"');
+                    i += 2;
+                } else {
+                    return Err(MalError::InvalidEscapeSequence {
+                        position: position_at(i),
+                    });
+                }
+                continue;
             }
-            continue;
-        }
 
-        if chars[i] == '"' {
-            // End of string
-            if i < token_len - 1 {
-                // We encountered an unescaped double quote
-                return Err(MalError::UnbalancedDoubleQuotes);
-            } else {
-                return Ok(MalData::String(parsed_string));
+            if chars[i].1 == '"' {
+                // End of string
+                if i < token_len - 1 {
+                    // We encountered an unescaped double quote
+                    return Err(MalError::UnbalancedDoubleQuotes {
+                        position: position_at(i),
+                        at_end_of_input: false,
+                    });
+                } else {
+                    return Ok(MalData::String(parsed_string));
+                }
             }
+
+            parsed_string.push(chars[i].1);
+            i += 1;
         }
 
-        parsed_string.push(chars[i]);
-        i += 1;
+        Err(MalError::UnbalancedDoubleQuotes {
+            position: position_at(0),
+            at_end_of_input,
+        })
     }
-
-    Err(MalError::UnbalancedDoubleQuotes)
 }
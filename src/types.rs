@@ -1,10 +1,12 @@
 use std::{
+    cell::RefCell,
     collections::hash_map,
     fmt::{Debug, Display},
+    hash::{DefaultHasher, Hash, Hasher},
     rc::Rc,
 };
 
-use crate::error::MalError;
+use crate::{env::Env, error::MalError, printer::pr_str};
 
 type MalFunction = dyn Fn(&[MalData]) -> Result<MalData, MalError>;
 
@@ -19,15 +21,32 @@ impl Debug for MalNativeFunction {
 
 impl From<Box<dyn Fn(&[MalData]) -> Result<MalData, MalError>>> for MalData {
     fn from(value: Box<dyn Fn(&[MalData]) -> Result<MalData, MalError>>) -> Self {
-        MalData::MalNativeFunction(MalNativeFunction(Rc::new(value)))
+        MalData::MalNativeFunction(MalNativeFunction(Rc::new(value)), None)
+    }
+}
+
+/// Metadata attached with `with-meta`, which does not affect equality or printing.
+pub type MalMeta = Option<Rc<MalData>>;
+
+#[derive(Clone)]
+pub struct MalClosure {
+    pub params: Vec<String>,
+    pub body: Rc<MalData>,
+    pub env: Rc<RefCell<Env>>,
+    pub is_macro: bool,
+}
+
+impl Debug for MalClosure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn>")
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum MalData {
-    List(Vec<MalData>),
-    Vector(Vec<MalData>),
-    HashMap(hash_map::HashMap<MalHashMapKey, MalData>),
+    List(Vec<MalData>, MalMeta),
+    Vector(Vec<MalData>, MalMeta),
+    HashMap(hash_map::HashMap<MalHashMapKey, MalData>, MalMeta),
     Integer(i64),
     Symbol(String),
     Nil,
@@ -35,7 +54,147 @@ pub enum MalData {
     False,
     String(String),
     Keyword(String),
-    MalNativeFunction(MalNativeFunction),
+    MalNativeFunction(MalNativeFunction, MalMeta),
+    MalClosure(MalClosure, MalMeta),
+    Atom(Rc<RefCell<MalData>>),
+}
+
+/// Structural equality. Lists and vectors with equal elements are equal, hash
+/// maps compare by content, functions and atoms compare by identity, and
+/// metadata is ignored.
+impl PartialEq for MalData {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                MalData::List(a, _) | MalData::Vector(a, _),
+                MalData::List(b, _) | MalData::Vector(b, _),
+            ) => a == b,
+            (MalData::HashMap(a, _), MalData::HashMap(b, _)) => a == b,
+            (MalData::Integer(a), MalData::Integer(b)) => a == b,
+            (MalData::Symbol(a), MalData::Symbol(b)) => a == b,
+            (MalData::String(a), MalData::String(b)) => a == b,
+            (MalData::Keyword(a), MalData::Keyword(b)) => a == b,
+            (MalData::Nil, MalData::Nil)
+            | (MalData::True, MalData::True)
+            | (MalData::False, MalData::False) => true,
+            (MalData::MalNativeFunction(a, _), MalData::MalNativeFunction(b, _)) => {
+                Rc::ptr_eq(&a.0, &b.0)
+            }
+            (MalData::MalClosure(a, _), MalData::MalClosure(b, _)) => {
+                Rc::ptr_eq(&a.body, &b.body) && Rc::ptr_eq(&a.env, &b.env)
+            }
+            (MalData::Atom(a), MalData::Atom(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for MalData {}
+
+impl Hash for MalData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            // Lists and vectors hash alike since they compare equal
+            MalData::List(sequence, _) | MalData::Vector(sequence, _) => {
+                state.write_u8(0);
+                sequence.hash(state);
+            }
+            MalData::HashMap(hash_map, _) => {
+                // Combine the entry hashes in an order-independent way
+                state.write_u8(1);
+                let combined = hash_map
+                    .iter()
+                    .map(|entry| {
+                        let mut hasher = DefaultHasher::new();
+                        entry.hash(&mut hasher);
+                        hasher.finish()
+                    })
+                    .fold(0u64, u64::wrapping_add);
+                state.write_usize(hash_map.len());
+                state.write_u64(combined);
+            }
+            MalData::Integer(integer) => {
+                state.write_u8(2);
+                integer.hash(state);
+            }
+            MalData::Symbol(symbol) => {
+                state.write_u8(3);
+                symbol.hash(state);
+            }
+            MalData::Nil => state.write_u8(4),
+            MalData::True => state.write_u8(5),
+            MalData::False => state.write_u8(6),
+            MalData::String(string) => {
+                state.write_u8(7);
+                string.hash(state);
+            }
+            MalData::Keyword(keyword) => {
+                state.write_u8(8);
+                keyword.hash(state);
+            }
+            MalData::MalNativeFunction(f, _) => {
+                state.write_u8(9);
+                std::ptr::hash(Rc::as_ptr(&f.0), state);
+            }
+            MalData::MalClosure(f, _) => {
+                state.write_u8(10);
+                std::ptr::hash(Rc::as_ptr(&f.body), state);
+                std::ptr::hash(Rc::as_ptr(&f.env), state);
+            }
+            MalData::Atom(atom) => {
+                state.write_u8(11);
+                std::ptr::hash(Rc::as_ptr(atom), state);
+            }
+        }
+    }
+}
+
+impl From<bool> for MalData {
+    fn from(value: bool) -> Self {
+        if value {
+            MalData::True
+        } else {
+            MalData::False
+        }
+    }
+}
+
+impl MalData {
+    pub fn as_integer(&self) -> Result<i64, MalError> {
+        match self {
+            MalData::Integer(integer) => Ok(*integer),
+            _ => Err(MalError::TypeError(self.clone())),
+        }
+    }
+
+    pub fn as_string(&self) -> Result<&str, MalError> {
+        match self {
+            MalData::String(string) => Ok(string),
+            _ => Err(MalError::TypeError(self.clone())),
+        }
+    }
+
+    /// Returns the elements of a list or vector.
+    pub fn as_sequence(&self) -> Result<&[MalData], MalError> {
+        match self {
+            MalData::List(sequence, _) | MalData::Vector(sequence, _) => Ok(sequence),
+            _ => Err(MalError::TypeError(self.clone())),
+        }
+    }
+
+    pub fn as_hash_map(&self) -> Result<&hash_map::HashMap<MalHashMapKey, MalData>, MalError> {
+        match self {
+            MalData::HashMap(hash_map, _) => Ok(hash_map),
+            _ => Err(MalError::TypeError(self.clone())),
+        }
+    }
+
+    pub fn as_atom(&self) -> Result<&Rc<RefCell<MalData>>, MalError> {
+        match self {
+            MalData::Atom(atom) => Ok(atom),
+            _ => Err(MalError::TypeError(self.clone())),
+        }
+    }
 }
 
 #[derive(Hash, PartialEq, Eq, Debug, Clone)]
@@ -60,68 +219,13 @@ impl TryFrom<MalData> for MalHashMapKey {
         match value {
             MalData::String(string) => Ok(MalHashMapKey::String(string)),
             MalData::Keyword(keyword) => Ok(MalHashMapKey::Keyword(keyword)),
-            _ => Err(MalError::InvalidHashMapKey),
+            _ => Err(MalError::InvalidHashMapKey { position: None }),
         }
     }
 }
 
-fn escape_mal_string(string: &str) -> String {
-    string
-        .replace("\\", "\\\\")
-        .replace("\"", "\\\"")
-        .replace("\n", "\\n")
-}
-
-// TODO: improve performance for the Display impl for List
 impl Display for MalData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            MalData::List(list) => {
-                write!(
-                    f,
-                    "({})",
-                    list.iter()
-                        .map(|el| format!("{}", el))
-                        .collect::<Vec<String>>()
-                        .join(" ")
-                )
-            }
-            MalData::Vector(vector) => {
-                write!(
-                    f,
-                    "[{}]",
-                    vector
-                        .iter()
-                        .map(|el| format!("{}", el))
-                        .collect::<Vec<String>>()
-                        .join(" ")
-                )
-            }
-            MalData::HashMap(hash_map) => {
-                write!(
-                    f,
-                    "{{{}}}",
-                    hash_map
-                        .iter()
-                        .map(|(key, value)| format!(
-                            "{} {}",
-                            MalData::from(key.clone()),
-                            value.clone()
-                        ))
-                        .collect::<Vec<String>>()
-                        .join(" ")
-                )
-            }
-            MalData::Integer(integer) => write!(f, "{}", integer),
-            MalData::Symbol(symbol) => write!(f, "{}", symbol),
-            MalData::Nil => write!(f, "nil"),
-            MalData::True => write!(f, "true"),
-            MalData::False => write!(f, "false"),
-            MalData::String(string) => write!(f, "\"{}\"", escape_mal_string(string)),
-            MalData::Keyword(keyword) => write!(f, ":{}", keyword),
-            MalData::MalNativeFunction(_) => {
-                write!(f, "<fn>")
-            }
-        }
+        write!(f, "{}", pr_str(self, true))
     }
 }